@@ -0,0 +1,30 @@
+use serde_json::Value;
+use std::{env, fs, path::Path};
+
+const SENSOR_RIG_DEFAULT: &str = "assets/sensor-rigs/default.json";
+
+// the observation has one input per ray of the sensor rig, SENSOR_RIG picks the rig at build time
+fn main() {
+    println!("cargo:rerun-if-env-changed=SENSOR_RIG");
+    let rig_path = env::var("SENSOR_RIG").unwrap_or_else(|_| SENSOR_RIG_DEFAULT.to_string());
+    println!("cargo:rerun-if-changed={rig_path}");
+    let rig: Value = serde_json::from_str(&fs::read_to_string(&rig_path).unwrap()).unwrap();
+    // same count as SensorRig::ray_count, the listed angles and the fan rays of every mount
+    let ray_count: u64 = rig["mounts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|mount| {
+            mount["angles_deg"].as_array().map_or(0, |a| a.len() as u64)
+                + mount["fan"]["count"].as_u64().unwrap_or(0)
+        })
+        .sum();
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("sensor_rig.rs");
+    fs::write(
+        out,
+        format!(
+            "pub const SENSOR_RIG: &str = {rig_path:?};\npub const SENSOR_COUNT: usize = {ray_count};\n"
+        ),
+    )
+    .unwrap();
+}
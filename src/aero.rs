@@ -0,0 +1,78 @@
+use crate::{car::Car, suspension::Suspension};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// drag and lift coefficients are already multiplied by the frontal area, in m2
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AeroDef {
+    pub drag_area: f32,
+    #[serde(default)]
+    pub downforce_front: f32,
+    #[serde(default)]
+    pub downforce_rear: f32,
+    #[serde(default = "default_air_density")]
+    pub air_density: f32,
+}
+fn default_air_density() -> f32 {
+    1.225
+}
+
+#[derive(Component, Debug)]
+pub struct Aero {
+    pub def: AeroDef,
+    // newtons of the last step
+    pub drag: f32,
+    pub downforce: [f32; 2],
+}
+
+impl Aero {
+    pub fn new(def: &AeroDef) -> Self {
+        Self {
+            def: def.clone(),
+            drag: 0.,
+            downforce: [0.; 2],
+        }
+    }
+}
+
+pub fn aero_system(
+    mut q_car: Query<(
+        &Transform,
+        &Velocity,
+        &ReadMassProperties,
+        &Suspension,
+        &mut Aero,
+        &mut ExternalForce,
+    )>,
+) {
+    for (t, v, mass, suspension, mut aero, mut force) in q_car.iter_mut() {
+        let dynamic_pressure = 0.5 * aero.def.air_density;
+        let speed = v.linvel.length();
+        let drag = -v.linvel * speed * dynamic_pressure * aero.def.drag_area;
+
+        // downforce grows with the forward speed only and pushes on the axle centers
+        let forward = t.rotation.mul_vec3(Vec3::Z);
+        let down = t.rotation.mul_vec3(-Vec3::Y);
+        let v_long_sq = v.linvel.dot(forward).powi(2);
+        let com = t.translation + t.rotation.mul_vec3(mass.0.local_center_of_mass);
+        let anchors = suspension.anchors;
+        let axles = [
+            (anchors[0] + anchors[1]) / 2.,
+            (anchors[2] + anchors[3]) / 2.,
+        ];
+        let coefficients = [aero.def.downforce_front, aero.def.downforce_rear];
+        let mut total = drag;
+        let mut torque = Vec3::ZERO;
+        for i in 0..2 {
+            let downforce = dynamic_pressure * coefficients[i] * v_long_sq;
+            aero.downforce[i] = downforce;
+            let point = t.translation + t.rotation.mul_vec3(axles[i]);
+            total += down * downforce;
+            torque += (point - com).cross(down * downforce);
+        }
+        aero.drag = drag.length();
+        force.force = total;
+        force.torque = torque;
+    }
+}
@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+
+const GRAVITY: f32 = 9.81;
+
+// toggleable driver aids, every car carries its own so runs with and without them can be compared
+#[derive(Component, Debug)]
+pub struct DriverAssists {
+    pub traction_control: bool,
+    pub stability_control: bool,
+    pub steering_assist: bool,
+    // drive slip ratio above which traction control cuts torque
+    pub tc_slip: f32,
+    // share of the drive torque kept on a spinning wheel
+    pub tc_cut: f32,
+    // brake pressure per rad/s of yaw rate error
+    pub esc_gain: f32,
+    // rad/s of yaw rate error ignored by stability control
+    pub esc_deadband: f32,
+    // lateral acceleration the car is assumed to manage, in g
+    pub max_lateral_g: f32,
+    pub tc_active: [bool; 4],
+    pub esc_pressure: [f32; 4],
+}
+
+impl DriverAssists {
+    pub fn new(traction_control: bool, stability_control: bool, steering_assist: bool) -> Self {
+        Self {
+            traction_control,
+            stability_control,
+            steering_assist,
+            tc_slip: 0.15,
+            tc_cut: 0.3,
+            esc_gain: 2.,
+            esc_deadband: 0.05,
+            max_lateral_g: 1.,
+            tc_active: [false; 4],
+            esc_pressure: [0.; 4],
+        }
+    }
+    // drive torque factor for every wheel
+    pub fn traction(&mut self, slip_ratio: [f32; 4]) -> [f32; 4] {
+        let mut factor = [1.; 4];
+        for i in 0..4 {
+            self.tc_active[i] = self.traction_control && slip_ratio[i] > self.tc_slip;
+            if self.tc_active[i] {
+                factor[i] = self.tc_cut;
+            }
+        }
+        factor
+    }
+    // yaw rate of a car following its steered front wheels without sliding, capped by grip
+    pub fn desired_yaw_rate(&self, v_long: f32, steer_angle: f32, wheelbase: f32) -> f32 {
+        let kinematic = v_long * steer_angle.tan() / wheelbase.max(0.1);
+        let grip_limit = self.max_lateral_g * GRAVITY / v_long.abs().max(1.);
+        kinematic.clamp(-grip_limit, grip_limit)
+    }
+    // extra brake pressure per wheel, brake_moment is the yaw moment sign braking each wheel creates
+    pub fn stability(
+        &mut self,
+        yaw_rate: f32,
+        desired_yaw_rate: f32,
+        brake_moment: [f32; 4],
+    ) -> [f32; 4] {
+        self.esc_pressure = [0.; 4];
+        let error = desired_yaw_rate - yaw_rate;
+        if !self.stability_control || error.abs() < self.esc_deadband {
+            return self.esc_pressure;
+        }
+        // oversteer is corrected on the front axle, understeer on the rear one
+        let oversteer = yaw_rate.abs() > desired_yaw_rate.abs();
+        let candidates = match oversteer {
+            true => [0, 1],
+            false => [2, 3],
+        };
+        let pressure = ((error.abs() - self.esc_deadband) * self.esc_gain).min(1.);
+        for i in candidates {
+            if brake_moment[i] * error > 0. {
+                self.esc_pressure[i] = pressure;
+            }
+        }
+        self.esc_pressure
+    }
+    // largest steering angle keeping the lateral acceleration within grip
+    pub fn steering_limit(&self, angle: f32, speed: f32, wheelbase: f32) -> f32 {
+        if !self.steering_assist || speed < 1. {
+            return angle;
+        }
+        let max_angle = (wheelbase * self.max_lateral_g * GRAVITY / speed.powi(2)).atan();
+        angle.clamp(-max_angle, max_angle)
+    }
+}
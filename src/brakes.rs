@@ -0,0 +1,77 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrakeDef {
+    // Nm of all four wheels together at full pedal
+    pub max_torque: f32,
+    // share of the brake torque on the front axle
+    pub bias: f32,
+    // Nm on each rear wheel
+    pub handbrake_torque: f32,
+    #[serde(default)]
+    pub abs: bool,
+    // abs releases a wheel once its slip ratio drops below -abs_slip
+    #[serde(default = "default_abs_slip")]
+    pub abs_slip: f32,
+}
+fn default_abs_slip() -> f32 {
+    0.15
+}
+
+// share of the pedal pressure kept on a wheel abs is releasing
+const ABS_RELEASE: f32 = 0.2;
+
+// wheel order follows car.wheels: front left, front right, back left, back right
+#[derive(Component, Debug)]
+pub struct Brakes {
+    pub def: BrakeDef,
+    pub abs: bool,
+    pub wheel_inertia: f32,
+    // 0..1 pressure reaching every wheel after abs
+    pub pressure: [f32; 4],
+    pub abs_active: [bool; 4],
+}
+
+impl Brakes {
+    pub fn new(def: &BrakeDef, wheel_inertia: f32) -> Self {
+        Self {
+            def: def.clone(),
+            abs: def.abs,
+            wheel_inertia,
+            pressure: [0.; 4],
+            abs_active: [false; 4],
+        }
+    }
+    // returns brake torques opposing the wheel spin, spin is rad/s
+    pub fn update(
+        &mut self,
+        pedal: [f32; 4],
+        handbrake: f32,
+        wheel_spin: [f32; 4],
+        slip_ratio: [f32; 4],
+        d_seconds: f32,
+    ) -> [f32; 4] {
+        let mut torque = [0.; 4];
+        for i in 0..4 {
+            let front = i < 2;
+            self.abs_active[i] = self.abs && pedal[i] > 0. && slip_ratio[i] < -self.def.abs_slip;
+            self.pressure[i] = match self.abs_active[i] {
+                true => pedal[i] * ABS_RELEASE,
+                false => pedal[i],
+            };
+            let axle_share = match front {
+                true => self.def.bias,
+                false => 1. - self.def.bias,
+            };
+            let mut wheel_torque = self.pressure[i] * self.def.max_torque * axle_share / 2.;
+            if !front {
+                wheel_torque += handbrake * self.def.handbrake_torque;
+            }
+            // stop the wheel at most, never spin it the other way within one step
+            let stopping_torque = self.wheel_inertia * wheel_spin[i].abs() / d_seconds.max(1e-4);
+            torque[i] = -wheel_spin[i].signum() * wheel_torque.min(stopping_torque);
+        }
+        torque
+    }
+}
@@ -1,17 +1,29 @@
 use crate::{
+    aero::*,
+    assists::*,
+    brakes::*,
     config::*,
+    drivetrain::*,
     mesh::*,
-    nn::{dqn_bevy::*, params::SENSOR_COUNT},
+    nn::{dqn_bevy::*, params::STEERING_SPEEDLIMIT_KMH},
+    noise::*,
+    randomization::*,
+    sensor::*,
+    surface::SurfaceKind,
+    suspension::*,
     track::*,
+    tyre::*,
+    vehicle::*,
+    weather::Weather,
 };
 use bevy::prelude::*;
 use bevy_prototype_debug_lines::DebugLines;
 use bevy_rapier3d::{
     parry::shape::Cylinder,
     prelude::*,
-    rapier::prelude::{JointAxesMask, JointAxis},
+    rapier::prelude::{InteractionGroups, JointAxesMask, JointAxis, MotorModel},
 };
-use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, FRAC_PI_8, PI};
+use std::f32::consts::{FRAC_PI_4, FRAC_PI_8, PI};
 
 pub const FRAC_PI_16: f32 = FRAC_PI_8 / 2.;
 
@@ -41,13 +53,19 @@ pub struct CarSize {
 #[derive(Component, Debug)]
 pub struct Car {
     pub size: CarSize,
-    pub sensor_config: [(Vec3, Quat); SENSOR_COUNT],
+    pub sensor_config: Vec<(Vec3, Quat)>,
     pub sensor_inputs: Vec<f32>,
+    pub wheel_slip: [f32; 4],
+    pub wheel_surface: [SurfaceKind; 4],
     pub gas: f32,
     pub brake: f32,
+    pub handbrake: f32,
     pub steering: f32,
     pub wheels: Vec<Entity>,
     pub wheel_max_torque: f32,
+    pub steering_lock: f32,
+    pub steering_speed_limit_kmh: f32,
+    pub vehicle: usize,
     pub init_transform: Transform,
     pub reset_at: Option<f64>,
 
@@ -55,6 +73,8 @@ pub struct Car {
     pub init_meters: f32,
     pub meters: f32,
     pub lap: usize,
+    pub lap_started_at: Option<f64>,
+    pub lap_time: Option<f32>,
     pub line_dir: Vec3,
     pub line_pos: Vec3,
     pub place: usize,
@@ -70,53 +90,22 @@ impl Default for Car {
         let hl = 2.2;
         Self {
             size: CarSize { hw, hh, hl },
-            sensor_inputs: vec![0.; SENSOR_COUNT],
-            sensor_config: [
-                // front
-                (hw, hl, 0.),
-                (0., hl, 0.),
-                (-hw, hl, 0.),
-                (hw, hl, FRAC_PI_16 / 2.),
-                (-hw, hl, -FRAC_PI_16 / 2.),
-                (hw, hl, FRAC_PI_16),
-                (-hw, hl, -FRAC_PI_16),
-                (hw, hl, FRAC_PI_16 + FRAC_PI_16 / 2.),
-                (-hw, hl, -FRAC_PI_16 - FRAC_PI_16 / 2.),
-                (hw, hl, FRAC_PI_8),
-                (-hw, hl, -FRAC_PI_8),
-                (hw, hl, FRAC_PI_8 + FRAC_PI_16),
-                (-hw, hl, -FRAC_PI_8 - FRAC_PI_16),
-                (hw, hl, FRAC_PI_4),
-                (-hw, hl, -FRAC_PI_4),
-                // front > PI/4
-                (hw, hl, FRAC_PI_4 + FRAC_PI_16),
-                (-hw, hl, -FRAC_PI_4 - FRAC_PI_16),
-                (hw, hl, FRAC_PI_4 + FRAC_PI_8),
-                (-hw, hl, -FRAC_PI_4 - FRAC_PI_8),
-                (hw, hl, FRAC_PI_4 + FRAC_PI_8 + FRAC_PI_16),
-                (-hw, hl, -FRAC_PI_4 - FRAC_PI_8 - FRAC_PI_16),
-                (hw, hl, FRAC_PI_2),
-                (-hw, hl, -FRAC_PI_2),
-                // side
-                (hw, 0., FRAC_PI_2),
-                (-hw, 0., -FRAC_PI_2),
-                // back
-                (hw, -hl, PI),
-                (-hw, -hl, PI),
-                (hw, -hl, PI - FRAC_PI_4),
-                (-hw, -hl, PI + FRAC_PI_4),
-                (hw, -hl, PI - FRAC_PI_2),
-                (-hw, -hl, PI + FRAC_PI_2),
-            ]
-            .map(|(w, l, r)| (Vec3::new(w, 0.1, l), Quat::from_rotation_y(r))),
+            sensor_inputs: vec![],
+            sensor_config: vec![],
+            wheel_slip: [0.; 4],
+            wheel_surface: [SurfaceKind::Asphalt; 4],
             gas: 0.,
             brake: 0.,
+            handbrake: 0.,
             steering: 0.,
             prev_steering: 0.,
             prev_torque: 0.,
             prev_dir: 0.,
             wheels: Vec::new(),
             wheel_max_torque: 1000.,
+            steering_lock: FRAC_PI_4,
+            steering_speed_limit_kmh: STEERING_SPEEDLIMIT_KMH,
+            vehicle: 0,
             init_transform: Transform::default(),
             reset_at: None,
 
@@ -125,6 +114,8 @@ impl Default for Car {
             meters: 0.,
             place: 0,
             lap: 0,
+            lap_started_at: None,
+            lap_time: None,
             line_dir: Vec3::ZERO,
             line_pos: Vec3::ZERO,
         }
@@ -146,52 +137,78 @@ pub fn car_start_system(
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut config: ResMut<Config>,
     asset_server: Res<AssetServer>,
+    mut rng: ResMut<SimRng>,
 ) {
-    let car_gl: Handle<Scene> = asset_server.load("car-race.glb#Scene0");
-    config.car_scene = Some(car_gl);
+    load_vehicles(&mut config, &asset_server, &mut meshes, &mut materials);
+    config.sensor_rig = SensorRig::load(&config.sensor_rig_path);
+    config.randomization = RandomizationProfile::load(&config.randomization_path);
 
     for i in 0..config.cars_count {
         let is_hid = i == 0;
         let (transform, init_meters) = config.get_transform_by_index(i);
+        let sample = config.randomization.sample(&mut rng);
         spawn_car(
             &mut commands,
             &mut meshes,
             &mut materials,
-            &config.car_scene.as_ref().unwrap(),
             is_hid,
             transform,
             i,
             init_meters,
-            config.max_torque,
+            &config,
+            sample,
         );
     }
 }
 
+// vehicle definitions and their render models, shared with the replay playback
+pub fn load_vehicles(
+    config: &mut Config,
+    asset_server: &AssetServer,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) {
+    config.vehicles = config
+        .vehicle_paths
+        .iter()
+        .map(|path| VehicleDef::load(path))
+        .collect();
+    let models: Vec<VehicleModel> = config
+        .vehicles
+        .iter()
+        .map(|v| VehicleModel::load(v, asset_server, meshes, materials))
+        .collect();
+    config.vehicle_models = models;
+}
+
 pub fn spawn_car(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<StandardMaterial>>,
-    car_gl: &Handle<Scene>,
     is_hid: bool,
     transform: Transform,
     index: usize,
     init_meters: f32,
-    max_torque: f32,
+    config: &Config,
+    sample: PhysicsSample,
 ) -> Entity {
+    let vehicle_i = index % config.vehicles.len();
+    let vehicle = &config.vehicles[vehicle_i];
+    let (chassis, wheels_def) = (&vehicle.chassis, &vehicle.wheels);
     let size = CarSize {
-        hw: 1.,
-        hh: 0.35,
-        hl: 2.2,
+        hw: chassis.half_width,
+        hh: chassis.half_height,
+        hl: chassis.half_length,
     };
-    let wheel_front_r: f32 = 0.4;
-    let wheel_back_r: f32 = 0.401;
-    let wheel_front_hw: f32 = 0.2;
-    let wheel_back_hw: f32 = 0.25;
-    let ride_height = 0.08;
+    let wheel_front_r: f32 = wheels_def.front.radius * sample.wheel_radius_scale;
+    let wheel_back_r: f32 = wheels_def.back.radius * sample.wheel_radius_scale;
+    let wheel_front_hw: f32 = wheels_def.front.half_width;
+    let wheel_back_hw: f32 = wheels_def.back.half_width;
+    let ride_height = wheels_def.ride_height;
     let shift = Vec3::new(
-        size.hw - wheel_front_hw - 0.1,
+        size.hw - wheel_front_hw - wheels_def.side_inset,
         -size.hh + wheel_front_r - ride_height,
-        size.hl - wheel_front_r - 0.5,
+        size.hl - wheel_front_r - wheels_def.axle_inset,
     );
     let car_anchors: [Vec3; 4] = [
         Vec3::new(shift.x, shift.y, shift.z),
@@ -203,11 +220,11 @@ pub fn spawn_car(
     let mut wheels: Vec<Entity> = vec![];
     let mut joints: Vec<GenericJoint> = vec![];
     for i in 0..4 {
-        let (is_front, _is_left): (bool, bool) = match i {
-            0 => (true, false),
-            1 => (true, true),
-            2 => (false, false),
-            _ => (false, true),
+        let (is_front, is_left): (bool, bool) = match i {
+            0 => (true, true),
+            1 => (true, false),
+            2 => (false, true),
+            _ => (false, false),
         };
         let wheel_hw = match is_front {
             true => wheel_front_hw,
@@ -217,21 +234,25 @@ pub fn spawn_car(
             true => wheel_front_r,
             false => wheel_back_r,
         };
-        let joint_mask = // JointAxesMask::X
-            // | JointAxesMask::Y // vertical suspension
-            // | JointAxesMask::Z // tire suspension along car
+        let joint_mask = JointAxesMask::X
+            // | JointAxesMask::Y // vertical suspension, sprung by the Y motor
+            | JointAxesMask::Z
             // | JointAxesMask::ANG_X // wheel main axis
-            JointAxesMask::ANG_Y
+            | JointAxesMask::ANG_Y
             | JointAxesMask::ANG_Z;
 
+        let spring = match is_front {
+            true => &vehicle.suspension.front,
+            false => &vehicle.suspension.back,
+        };
         let joint = GenericJointBuilder::new(joint_mask)
             .local_axis1(Vec3::X)
             .local_axis2(Vec3::Y)
             .local_anchor1(car_anchors[i])
             .local_anchor2(Vec3::ZERO)
-            .set_motor(JointAxis::X, 0., 0., 10e10, 1.)
-            .set_motor(JointAxis::Y, 0., 0., 50_000., 100.)
-            .set_motor(JointAxis::Z, 0., 0., 10e10, 1.)
+            .motor_model(JointAxis::Y, MotorModel::ForceBased)
+            .set_motor(JointAxis::Y, 0., 0., spring.stiffness, spring.damping)
+            .limits(JointAxis::Y, [-spring.travel, spring.travel])
             .build();
         joints.push(joint);
 
@@ -260,11 +281,22 @@ pub fn spawn_car(
                 wheel_border_radius,
             ))
             .insert(ColliderScale::Absolute(Vec3::ONE))
-            .insert(CollisionGroups::new(CAR_TRAINING_GROUP, STATIC_GROUP))
-            .insert(Friction {
-                combine_rule: CoefficientCombineRule::Max,
-                coefficient: 5.0,
-                ..default()
+            .insert(CollisionGroups::new(
+                CAR_TRAINING_GROUP,
+                STATIC_GROUP | GROUND_GROUP,
+            ))
+            .insert(match vehicle.tyre {
+                // the tyre model produces all grip, the collider only supports the wheel
+                Some(_) => Friction {
+                    combine_rule: CoefficientCombineRule::Min,
+                    coefficient: 0.,
+                    ..default()
+                },
+                None => Friction {
+                    combine_rule: CoefficientCombineRule::Max,
+                    coefficient: wheels_def.friction * sample.tyre_friction_scale,
+                    ..default()
+                },
             })
             .insert(Restitution::coefficient(0.))
             .insert(Damping {
@@ -273,8 +305,8 @@ pub fn spawn_car(
             })
             .insert(ColliderMassProperties::MassProperties(MassProperties {
                 local_center_of_mass: Vec3::ZERO,
-                mass: 15.,
-                principal_inertia: Vec3::ONE * 0.3,
+                mass: wheels_def.mass,
+                principal_inertia: Vec3::ONE * wheels_def.inertia,
                 ..default()
             }))
             .insert(Wheel {
@@ -285,9 +317,14 @@ pub fn spawn_car(
             .insert(ExternalImpulse::default())
             .id();
         wheels.push(wheel_id);
+        // the collider has no friction under a tyre, so the sampled ground friction scales its grip
+        if let Some(tyre) = &vehicle.tyre {
+            let grip = sample.tyre_friction_scale * sample.ground_friction / GROUND_FRICTION;
+            commands.entity(wheel_id).insert(Tyre::new(tyre, grip));
+        }
 
         if is_front {
-            if is_front {
+            if is_left {
                 commands
                     .entity(wheel_id)
                     .insert(WheelFrontLeft)
@@ -304,44 +341,58 @@ pub fn spawn_car(
     }
     let carrr = Car {
         size: size.clone(),
+        sensor_config: config.sensor_rig.rays(&size),
+        sensor_inputs: vec![0.; config.sensor_rig.ray_count()],
         wheels: wheels.clone(),
-        wheel_max_torque: max_torque,
+        wheel_max_torque: vehicle.drivetrain.max_torque * sample.torque_scale,
+        steering_lock: vehicle.steering.lock_deg.to_radians(),
+        steering_speed_limit_kmh: vehicle.steering.speed_limit_kmh,
+        vehicle: vehicle_i,
         init_transform: transform,
         init_meters,
+        meters: init_meters,
         index,
         ..default()
     };
 
+    let model = config.vehicle_models[vehicle_i].clone();
+    let model_transform = vehicle.model.transform();
     let car_id = commands
         .spawn()
         .insert(Name::new("car"))
         .insert(Sleeping::disabled())
         .insert(carrr)
-        .insert(CarDqnPrev::new())
+        .insert(CarDqnPrev::new(init_meters))
+        .insert(CarFrameStack::new())
+        .insert(sample.clone())
+        .insert(SensorLatency::default())
+        .insert(ActuatorDelay::new(config.actuator_delay_steps))
+        .insert(Brakes::new(&vehicle.brakes, wheels_def.inertia))
+        .insert(Aero::new(&vehicle.aero))
+        .insert(DriverAssists::new(
+            config.traction_control,
+            config.stability_control,
+            config.steering_assist,
+        ))
         .insert(RigidBody::Dynamic)
         .insert(Ccd::enabled())
         .insert(Damping {
-            linear_damping: 0.05,
-            angular_damping: 20.0,
+            linear_damping: chassis.linear_damping,
+            angular_damping: chassis.angular_damping,
         })
         .insert(Velocity::zero())
         .insert(ExternalForce::default())
-        .insert_bundle(TransformBundle::from(transform))
+        .insert(ExternalImpulse::default())
+        .insert(Suspension::new(&vehicle.suspension, car_anchors))
+        .insert_bundle(SpatialBundle::from_transform(transform))
         .insert(ReadMassProperties::default())
-        .insert_bundle(SceneBundle {
-            scene: car_gl.clone(),
-            transform,
-            // .with_translation(Vec3::new(0., -0.75, 0.2))
-            // .with_rotation(Quat::from_rotation_y(PI))
-            // .with_scale(Vec3::ONE * 1.7),
-            ..default()
-        })
         .with_children(|children| {
+            model.spawn(children, model_transform);
             let collider_mass = ColliderMassProperties::MassProperties(MassProperties {
-                local_center_of_mass: Vec3::new(0., -size.hh, 0.),
-                mass: 1500.0,
+                local_center_of_mass: Vec3::from(chassis.center_of_mass) + sample.com_offset,
+                mass: chassis.mass * sample.mass_scale,
                 // https://www.nhtsa.gov/DOT/NHTSA/NRD/Multimedia/PDFs/VRTC/ca/capubs/sae1999-01-1336.pdf
-                principal_inertia: Vec3::new(5000., 5000., 2000.),
+                principal_inertia: Vec3::from(chassis.principal_inertia) * sample.mass_scale,
                 ..default()
             });
             let car_bradius = 0.05;
@@ -355,9 +406,12 @@ pub fn spawn_car(
                     car_bradius,
                 ))
                 .insert(ColliderScale::Absolute(Vec3::ONE))
-                .insert(Friction::coefficient(0.5))
+                .insert(Friction::coefficient(chassis.friction))
                 .insert(Restitution::coefficient(0.))
-                .insert(CollisionGroups::new(CAR_TRAINING_GROUP, STATIC_GROUP))
+                .insert(CollisionGroups::new(
+                    CAR_TRAINING_GROUP,
+                    STATIC_GROUP | GROUND_GROUP,
+                ))
                 .insert(CollidingEntities::default())
                 .insert(ActiveEvents::COLLISION_EVENTS)
                 .insert(ContactForceEventThreshold(0.1))
@@ -368,24 +422,68 @@ pub fn spawn_car(
     if is_hid {
         commands.entity(car_id).insert(HID);
     }
+    if let Some(powertrain) = &vehicle.drivetrain.powertrain {
+        commands.entity(car_id).insert(Drivetrain::new(powertrain));
+    }
+    if config.lidar {
+        commands.entity(car_id).insert(Lidar::default());
+    }
+    if config.depth_camera {
+        commands.entity(car_id).insert(DepthCamera::default());
+    }
     for (i, wheel_id) in wheels.iter().enumerate() {
         commands
             .entity(*wheel_id)
             .insert(ImpulseJoint::new(car_id, joints[i]));
     }
-    println!("car log: {car_id:?} {:?}", wheels);
+    println!("car log: {car_id:?} {} {:?}", vehicle.name, wheels);
     return car_id;
 }
 
+// same crash rule as the training loop, touching anything but the road
+pub fn is_crashing(
+    children: &Children,
+    q_colliding_entities: &Query<&CollidingEntities>,
+    q_name: &Query<&Name>,
+) -> bool {
+    children
+        .iter()
+        .any(|&child| match q_colliding_entities.get(child) {
+            Ok(colliding_entities) => colliding_entities.iter().any(|e| match q_name.get(e) {
+                Ok(name) => !name.contains(ASSET_ROAD),
+                Err(_) => false,
+            }),
+            Err(_) => false,
+        })
+}
+
 pub fn car_sensor_system(
     rapier_context: Res<RapierContext>,
     config: Res<Config>,
-    mut q_car: Query<(&mut Car, &GlobalTransform, &Transform), With<Car>>,
+    weather: Res<Weather>,
+    mut q_car: Query<
+        (
+            &mut Car,
+            &GlobalTransform,
+            &Transform,
+            &mut SensorLatency,
+            Option<&CarDqnPrev>,
+        ),
+        With<Car>,
+    >,
     mut lines: ResMut<DebugLines>,
+    mut rng: ResMut<SimRng>,
 ) {
-    let sensor_filter = QueryFilter::new().exclude_dynamic().exclude_sensors();
-    let dir = Vec3::Z * config.max_toi;
-    for (mut car, gt, t) in q_car.iter_mut() {
+    let rig = &config.sensor_rig;
+    let sensor_filter = QueryFilter::new()
+        .exclude_dynamic()
+        .exclude_sensors()
+        .groups(InteractionGroups::new(u32::MAX, rig.collision_groups));
+    let max_toi = rig.max_range;
+    // fog hides what is further away, readings keep the scale of the full range
+    let visible_toi = weather.visibility(max_toi);
+    let dir = Vec3::Z * max_toi;
+    for (mut car, gt, t, mut latency, dqn_prev) in q_car.iter_mut() {
         let mut origins: Vec<Vec3> = Vec::new();
         let mut dirs: Vec<Vec3> = Vec::new();
         let g_translation = gt.translation();
@@ -396,41 +494,49 @@ pub fn car_sensor_system(
             0.0,
             Color::rgba(0.5, 0.5, 0.5, 0.5),
         );
-        for a in 0..SENSOR_COUNT {
+        let ray_count = car.sensor_config.len();
+        for a in 0..ray_count {
             let (pos, far_quat) = car.sensor_config[a];
             let origin = g_translation + t.rotation.mul_vec3(pos);
             origins.push(origin);
-            let mut dir_vec = t.rotation.mul_vec3(far_quat.mul_vec3(dir));
-            dir_vec.y = 0.;
+            // rays follow the body on slopes, the ground is not in the rig's groups
+            let dir_vec = t.rotation.mul_vec3(far_quat.mul_vec3(dir));
             dirs.push(origin + dir_vec);
         }
 
-        let mut inputs: Vec<f32> = vec![0.; SENSOR_COUNT];
-        let mut hit_points: Vec<Vec3> = vec![Vec3::ZERO; SENSOR_COUNT];
+        let mut inputs: Vec<f32> = vec![0.; ray_count];
+        let mut hit_points: Vec<Vec3> = vec![Vec3::ZERO; ray_count];
         for (i, &ray_dir_pos) in dirs.iter().enumerate() {
             let ray_pos = origins[i];
             let ray_dir = (ray_dir_pos - ray_pos).normalize();
 
-            if let Some((_e, toi)) =
-                rapier_context.cast_ray(ray_pos, ray_dir, config.max_toi, false, sensor_filter)
-            {
+            let hit = rapier_context
+                .cast_ray(ray_pos, ray_dir, visible_toi, false, sensor_filter)
+                .map(|(_e, toi)| toi);
+            if let Some(toi) = rig.noise.apply(hit, visible_toi, &mut rng) {
                 hit_points[i] = ray_pos + ray_dir * toi;
                 if toi > 0. {
-                    inputs[i] = 1. - toi / config.max_toi;
+                    inputs[i] = 1. - toi / max_toi;
                     if config.show_rays {
-                        lines.line_colored(
-                            ray_pos,
-                            hit_points[i],
-                            0.0,
-                            Color::rgba(0.5, 0.3, 0.3, 0.5),
-                        );
+                        // red when close, or blue to yellow by saliency with the overlays on
+                        let color = match (config.nn_overlays, dqn_prev) {
+                            (true, Some(dqn_prev)) => {
+                                let s = dqn_prev.saliency.get(i).copied().unwrap_or(0.);
+                                Color::rgba(s, s, 1. - s, 0.3 + 0.7 * s)
+                            }
+                            _ => {
+                                let far = toi / max_toi;
+                                Color::rgba(1. - far, far, 0.2, 0.5)
+                            }
+                        };
+                        lines.line_colored(ray_pos, hit_points[i], 0.0, color);
                     }
                 } else {
                     inputs[i] = 0.;
                 }
             }
         }
-        car.sensor_inputs = inputs;
+        car.sensor_inputs = latency.push(inputs, rig.noise.latency_steps);
         // println!("inputs {:#?}", car.sensor_inputs);
     }
 }
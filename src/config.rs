@@ -3,16 +3,48 @@ use parry3d::shape::Polyline;
 use rand::Rng;
 use std::f32::consts::PI;
 
-use crate::nn::params::{CARS_COUNT, MAX_TOI, MAX_TORQUE};
+use crate::{
+    nn::{
+        params::{CARS_COUNT, OBS_DEPTH, OBS_LIDAR_SECTORS, SENSOR_RIG},
+        reward::RewardWeights,
+    },
+    noise::SimRng,
+    randomization::*,
+    sensor::*,
+    surface::*,
+    terrain::TERRAIN_DEFAULT,
+    vehicle::*,
+    weather::WEATHER_DEFAULT,
+};
 
 pub struct Config {
+    // names per track files like the ghost lap
+    pub track_name: String,
     pub translation: Vec3,
     pub quat: Quat,
     pub cars_count: usize,
     pub show_rays: bool,
+    // q-value bars above the cars and rays coloured by saliency
+    pub nn_overlays: bool,
     pub use_brain: bool,
-    pub max_torque: f32,
-    pub max_toi: f32,
+    pub sensor_rig_path: String,
+    pub sensor_rig: SensorRig,
+    pub lidar: bool,
+    pub depth_camera: bool,
+    pub export_perception: bool,
+    pub telemetry: bool,
+    // samples per second of every car
+    pub telemetry_hz: f32,
+    pub actuator_delay_steps: usize,
+    pub surfaces_path: String,
+    pub surfaces: SurfaceMap,
+    pub weather_path: String,
+    pub terrain_path: String,
+    pub traction_control: bool,
+    pub stability_control: bool,
+    pub steering_assist: bool,
+    pub randomization_path: String,
+    pub randomization: RandomizationProfile,
     pub hid_car: Option<Entity>,
     pub polyline: Option<Polyline>,
     pub segment_i: u32,
@@ -20,16 +52,36 @@ pub struct Config {
     pub meters: Vec<f32>,
     pub meters_shift: f32,
     pub track_length: f32,
-    pub car_scene: Option<Handle<Scene>>,
+    pub vehicle_paths: Vec<String>,
+    pub vehicles: Vec<VehicleDef>,
+    pub vehicle_models: Vec<VehicleModel>,
+    pub reward_weights: RewardWeights,
 }
 impl Default for Config {
     fn default() -> Self {
         Self {
+            track_name: "nurburgring-gp".to_string(),
             cars_count: CARS_COUNT,
             use_brain: false,
             show_rays: true,
-            max_torque: MAX_TORQUE,
-            max_toi: MAX_TOI,
+            nn_overlays: false,
+            sensor_rig_path: SENSOR_RIG.to_string(),
+            sensor_rig: SensorRig::default(),
+            lidar: OBS_LIDAR_SECTORS > 0,
+            depth_camera: OBS_DEPTH,
+            export_perception: false,
+            telemetry: false,
+            telemetry_hz: 10.,
+            actuator_delay_steps: 0,
+            surfaces_path: SURFACES_DEFAULT.to_string(),
+            surfaces: SurfaceMap::default(),
+            weather_path: WEATHER_DEFAULT.to_string(),
+            terrain_path: TERRAIN_DEFAULT.to_string(),
+            traction_control: false,
+            stability_control: false,
+            steering_assist: false,
+            randomization_path: RANDOMIZATION_DEFAULT.to_string(),
+            randomization: RandomizationProfile::default(),
             translation: Vec3::new(0., 0.1, 0.),
             quat: Quat::from_rotation_y(-PI * 0.225),
             hid_car: None,
@@ -39,7 +91,14 @@ impl Default for Config {
             meters: vec![],
             meters_shift: 0.,
             track_length: 0.,
-            car_scene: None,
+            vehicle_paths: vec![
+                VEHICLE_RACE.to_string(),
+                VEHICLE_HATCHBACK.to_string(),
+                VEHICLE_TRUCK.to_string(),
+            ],
+            vehicles: vec![],
+            vehicle_models: vec![],
+            reward_weights: RewardWeights::default(),
         }
     }
 }
@@ -50,9 +109,8 @@ impl Config {
         let transform = Transform::from_translation(translate).with_rotation(quat);
         return (transform, meters);
     }
-    pub fn get_transform_random(&self) -> (Transform, f32) {
-        let mut rng = rand::thread_rng();
-        let meters = rng.gen_range(0.0..self.track_length);
+    pub fn get_transform_random(&self, rng: &mut SimRng) -> (Transform, f32) {
+        let meters = rng.rng.gen_range(0.0..self.track_length);
         let (translate, quat) = self.get_transform_by_meter(meters);
         let transform = Transform::from_translation(translate).with_rotation(quat);
         return (transform, meters);
@@ -73,11 +131,24 @@ impl Config {
                 let a: Vec3 = segment.a.into();
                 let dir: Vec3 = segment.direction().unwrap().into();
                 let mut pos: Vec3 = a + dir * (shift - seg_meters);
-                pos.y = 0.4;
+                // the polyline follows the terrain, spawn just above it facing along the slope
+                pos.y += 0.4;
+                let rotation = Transform::from_translation(pos)
+                    .looking_at(pos - dir, Vec3::Y)
+                    .rotation;
 
-                return (pos, Quat::from_rotation_arc(Vec3::Z, dir));
+                return (pos, rotation);
             }
         }
         panic!();
     }
+    pub fn get_direction_by_meter(&self, meters: f32) -> Vec3 {
+        let polyline = self.polyline.as_ref().unwrap();
+        let shift = (meters + self.meters_shift).rem_euclid(self.track_length);
+        let segment_i = self.meters.partition_point(|&m| m <= shift).max(1) - 1;
+        match polyline.segment(segment_i as u32).direction() {
+            Some(dir) => Vec3::from(dir),
+            None => Vec3::Z,
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use crate::car::*;
+use crate::{car::*, drivetrain::Drivetrain, ghost::Ghost};
 use bevy::prelude::*;
 use bevy::{diagnostic::Diagnostics, diagnostic::FrameTimeDiagnosticsPlugin};
 use bevy_rapier3d::prelude::*;
@@ -10,6 +10,10 @@ pub struct MpsText;
 #[derive(Component)]
 pub struct KmphText;
 #[derive(Component)]
+pub struct GearText;
+#[derive(Component)]
+pub struct GhostText;
+#[derive(Component)]
 pub struct Leaderboard;
 #[derive(Component)]
 pub struct TrainerRecordDistanceText;
@@ -159,28 +163,65 @@ pub fn dash_speed_start_system(mut commands: Commands, asset_server: Res<AssetSe
             ..default()
         })
         .insert(KmphText);
+    commands
+        .spawn_bundle(TextBundle {
+            style: get_style(120.),
+            text: Text {
+                sections: vec![TextSection {
+                    value: "".to_string(),
+                    style: style.clone(),
+                }],
+                ..default()
+            },
+            ..default()
+        })
+        .insert(GearText);
+    commands
+        .spawn_bundle(TextBundle {
+            style: get_style(140.),
+            text: Text {
+                sections: vec![TextSection {
+                    value: "".to_string(),
+                    style: style.clone(),
+                }],
+                ..default()
+            },
+            ..default()
+        })
+        .insert(GhostText);
 }
 
 pub fn dash_speed_update_system(
     mut texts: ParamSet<(
         Query<&mut Text, With<MpsText>>,
         Query<&mut Text, With<KmphText>>,
+        Query<&mut Text, With<GearText>>,
+        Query<&mut Text, With<GhostText>>,
     )>,
-    mut cars: Query<(&Velocity, &Car, With<HID>)>,
-    wheels: Query<(&Velocity, &ExternalForce), With<Wheel>>,
+    mut cars: Query<(&Velocity, Option<&Drivetrain>, With<HID>)>,
+    ghost: Res<Ghost>,
 ) {
-    for (velocity, car, _) in cars.iter_mut() {
+    for (velocity, drivetrain, _) in cars.iter_mut() {
         let mps = velocity.linvel.length();
         let kmph = mps * 3.6;
         texts.p0().single_mut().sections[0].value = format!("mps {:.1}", mps);
         texts.p1().single_mut().sections[0].value = format!("kmph {:.1}", kmph);
-        let mut v_msg: String = "".to_string();
-        let mut f_msg: String = "".to_string();
-        for wheel_entity in car.wheels.iter() {
-            if let Ok((v, f)) = wheels.get(*wheel_entity) {
-                v_msg = v_msg + &format!("{:.1} ", v.angvel.length());
-                f_msg = f_msg + &format!("{:.1} ", f.torque.length());
+        texts.p2().single_mut().sections[0].value = match drivetrain {
+            Some(d) => {
+                let gear = match d.gear {
+                    -1 => "R".to_string(),
+                    0 => "N".to_string(),
+                    g => g.to_string(),
+                };
+                let mode = if d.automatic { "auto" } else { "manual" };
+                format!("gear {gear} {mode} rpm {:.0}", d.rpm)
             }
-        }
+            None => "".to_string(),
+        };
+        texts.p3().single_mut().sections[0].value = match (&ghost.best, ghost.delta) {
+            (Some(best), Some(delta)) => format!("ghost {:.2}s delta {delta:+.2}s", best.lap_time),
+            (Some(best), None) => format!("ghost {:.2}s", best.lap_time),
+            (None, _) => "".to_string(),
+        };
     }
 }
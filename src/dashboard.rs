@@ -0,0 +1,98 @@
+use crate::{
+    car::*,
+    nn::{dqn_bevy::*, util::action_label},
+};
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use egui::plot::{Bar, BarChart, Line, Plot, PlotPoints};
+
+const PLOT_HEIGHT: f32 = 80.;
+const POLAR_SIZE: f32 = 160.;
+
+// the training panel, toggled with Tab
+#[derive(Default)]
+pub struct TrainingDashboard {
+    pub open: bool,
+}
+
+pub fn training_dashboard_system(
+    input: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    mut dashboard: ResMut<TrainingDashboard>,
+    mut dqn: ResMut<DqnResource>,
+    mut cars_dqn: NonSendMut<CarsDqnResource>,
+    q_car: Query<(&Car, &CarDqnPrev), With<HID>>,
+) {
+    if input.just_pressed(KeyCode::Tab) {
+        dashboard.open = !dashboard.open;
+    }
+    if !dashboard.open {
+        return;
+    }
+    egui::Window::new("training")
+        .default_width(360.)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut dqn.training_paused, "pause training");
+                if ui.button("sync target network").clicked() {
+                    cars_dqn.tqn = cars_dqn.qn.clone();
+                    println!("networks sync forced");
+                }
+            });
+            let (min_eps, max_eps) = (dqn.min_eps, dqn.max_eps);
+            ui.add(egui::Slider::new(&mut dqn.eps, min_eps..=max_eps).text("epsilon"));
+
+            for name in ["loss", "episode_return", "epsilon"] {
+                ui.label(name);
+                let points = dqn.metrics.history(name);
+                Plot::new(name).height(PLOT_HEIGHT).show(ui, |plot_ui| {
+                    plot_ui.line(Line::new(PlotPoints::new(points)))
+                });
+            }
+
+            let (car, dqn_prev) = match q_car.get_single() {
+                Ok(car) => car,
+                Err(_) => return,
+            };
+            // the greedy action of the current observation is highlighted
+            let q_values = dqn_prev.q_values;
+            let best =
+                q_values
+                    .iter()
+                    .enumerate()
+                    .fold(0, |best, (i, q)| if *q > q_values[best] { i } else { best });
+            let bars: Vec<Bar> = q_values
+                .iter()
+                .enumerate()
+                .map(|(i, q)| {
+                    let bar = Bar::new(i as f64, *q as f64).name(action_label(i));
+                    match i == best {
+                        true => bar.fill(egui::Color32::LIGHT_GREEN),
+                        false => bar.fill(egui::Color32::LIGHT_BLUE),
+                    }
+                })
+                .collect();
+            ui.label("q values");
+            Plot::new("q_values")
+                .height(PLOT_HEIGHT)
+                .show(ui, |plot_ui| plot_ui.bar_chart(BarChart::new(bars)));
+
+            // car forward is up, ray length is the free distance of the range
+            ui.label("sensors");
+            let (response, painter) =
+                ui.allocate_painter(egui::Vec2::splat(POLAR_SIZE), egui::Sense::hover());
+            let center = response.rect.center();
+            let radius = POLAR_SIZE / 2.;
+            painter.circle_stroke(center, radius, egui::Stroke::new(1., egui::Color32::GRAY));
+            for (i, (_pos, quat)) in car.sensor_config.iter().enumerate() {
+                let dir = quat.mul_vec3(Vec3::Z);
+                let reach = 1. - car.sensor_inputs.get(i).copied().unwrap_or(0.);
+                let end = center + egui::vec2(-dir.x, -dir.z) * radius * reach;
+                let color = match reach < 1. {
+                    true => egui::Color32::from_rgb(220, 80, 80),
+                    false => egui::Color32::GRAY,
+                };
+                painter.line_segment([center, end], egui::Stroke::new(1.5, color));
+            }
+        });
+}
@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+// torque curve points are [rpm, Nm], linearly interpolated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineDef {
+    pub idle_rpm: f32,
+    pub redline_rpm: f32,
+    pub torque_curve: Vec<[f32; 2]>,
+}
+
+impl EngineDef {
+    pub fn torque(&self, rpm: f32) -> f32 {
+        if rpm >= self.redline_rpm {
+            return 0.;
+        }
+        let curve = &self.torque_curve;
+        match curve.iter().position(|p| p[0] >= rpm) {
+            None => curve.last().map_or(0., |p| p[1]),
+            Some(0) => curve[0][1],
+            Some(i) => {
+                let (a, b) = (curve[i - 1], curve[i]);
+                a[1] + (b[1] - a[1]) * (rpm - a[0]) / (b[0] - a[0])
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GearboxDef {
+    pub ratios: Vec<f32>,
+    pub reverse: f32,
+    pub final_drive: f32,
+    // seconds without drive while changing gear
+    pub shift_time: f32,
+    pub automatic: bool,
+    pub upshift_rpm: f32,
+    pub downshift_rpm: f32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DriveLayout {
+    Fwd,
+    Rwd,
+    Awd,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DifferentialKind {
+    Open,
+    Lsd,
+}
+
+// locking 0 behaves like an open differential, 1 sends all torque to the slower wheel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DifferentialDef {
+    pub kind: DifferentialKind,
+    #[serde(default)]
+    pub locking: f32,
+    // share of the torque going to the front axle with awd
+    #[serde(default = "default_front_split")]
+    pub front_split: f32,
+}
+fn default_front_split() -> f32 {
+    0.5
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowertrainDef {
+    pub engine: EngineDef,
+    pub gearbox: GearboxDef,
+    pub layout: DriveLayout,
+    pub differential: DifferentialDef,
+}
+
+// gear 0 is neutral, -1 reverse
+#[derive(Component, Debug)]
+pub struct Drivetrain {
+    pub def: PowertrainDef,
+    pub automatic: bool,
+    pub gear: i32,
+    pub rpm: f32,
+    pub throttle: f32,
+    pub shift_until: f64,
+    pub wheel_torque: [f32; 4],
+}
+
+impl Drivetrain {
+    pub fn new(def: &PowertrainDef) -> Self {
+        Self {
+            def: def.clone(),
+            automatic: def.gearbox.automatic,
+            gear: 1,
+            rpm: def.engine.idle_rpm,
+            throttle: 0.,
+            shift_until: 0.,
+            wheel_torque: [0.; 4],
+        }
+    }
+    pub fn ratio(&self) -> f32 {
+        let gearbox = &self.def.gearbox;
+        let gear_ratio = match self.gear {
+            0 => 0.,
+            g if g < 0 => -gearbox.reverse,
+            g => gearbox.ratios[(g as usize - 1).min(gearbox.ratios.len() - 1)],
+        };
+        gear_ratio * gearbox.final_drive
+    }
+    pub fn shift(&mut self, gear: i32, seconds: f64) {
+        let gear = gear.clamp(-1, self.def.gearbox.ratios.len() as i32);
+        if gear != self.gear {
+            self.gear = gear;
+            self.shift_until = seconds + self.def.gearbox.shift_time as f64;
+        }
+    }
+    pub fn shift_up(&mut self, seconds: f64) {
+        self.shift(self.gear + 1, seconds);
+    }
+    pub fn shift_down(&mut self, seconds: f64) {
+        self.shift(self.gear - 1, seconds);
+    }
+    pub fn driven(&self) -> [f32; 4] {
+        let front = self.def.differential.front_split;
+        match self.def.layout {
+            DriveLayout::Fwd => [1., 1., 0., 0.],
+            DriveLayout::Rwd => [0., 0., 1., 1.],
+            DriveLayout::Awd => [front, front, 1. - front, 1. - front],
+        }
+    }
+    // wheel_spin is rad/s in the rolling direction, ordered like car.wheels, returns wheel torques
+    pub fn update(
+        &mut self,
+        throttle: f32,
+        reverse: bool,
+        wheel_spin: [f32; 4],
+        seconds: f64,
+        d_seconds: f32,
+    ) -> [f32; 4] {
+        self.throttle += (throttle - self.throttle) * (d_seconds * 4.).min(1.);
+        if reverse && self.gear >= 0 {
+            self.shift(-1, seconds);
+        } else if !reverse && self.gear <= 0 && throttle > 0. {
+            self.shift(1, seconds);
+        }
+
+        let driven = self.driven();
+        let (mut spin, mut weight) = (0., 0.);
+        for i in 0..4 {
+            spin += wheel_spin[i].abs() * driven[i];
+            weight += driven[i];
+        }
+        let axle_rpm = spin / weight.max(f32::EPSILON) * 60. / (2. * PI);
+        self.rpm = (axle_rpm * self.ratio().abs()).max(self.def.engine.idle_rpm);
+
+        let (upshift_rpm, downshift_rpm) =
+            (self.def.gearbox.upshift_rpm, self.def.gearbox.downshift_rpm);
+        let top_gear = self.def.gearbox.ratios.len() as i32;
+        if self.automatic && self.gear > 0 && seconds >= self.shift_until {
+            if self.rpm > upshift_rpm && self.gear < top_gear {
+                self.shift_up(seconds);
+            } else if self.rpm < downshift_rpm && self.gear > 1 {
+                self.shift_down(seconds);
+            }
+        }
+
+        if seconds < self.shift_until {
+            self.wheel_torque = [0.; 4];
+            return self.wheel_torque;
+        }
+        let drive = self.def.engine.torque(self.rpm) * self.throttle * self.ratio();
+        let mut torque = [0.; 4];
+        for (left, right) in [(0, 1), (2, 3)] {
+            let axle = drive * driven[left];
+            if axle == 0. {
+                continue;
+            }
+            let (t_left, t_right) = self.split(axle, wheel_spin[left], wheel_spin[right]);
+            torque[left] = t_left;
+            torque[right] = t_right;
+        }
+        self.wheel_torque = torque;
+        torque
+    }
+    // the faster wheel of an axle gives torque to the slower one with a limited slip differential
+    fn split(&self, axle: f32, spin_left: f32, spin_right: f32) -> (f32, f32) {
+        let differential = &self.def.differential;
+        let bias = match differential.kind {
+            DifferentialKind::Open => 0.,
+            DifferentialKind::Lsd => {
+                let total = spin_left.abs() + spin_right.abs();
+                match total > 1. {
+                    true => differential.locking * (spin_left - spin_right) / total,
+                    false => 0.,
+                }
+            }
+        };
+        (axle * 0.5 * (1. - bias), axle * 0.5 * (1. + bias))
+    }
+}
@@ -1,11 +1,29 @@
-use crate::{car::*, config::*, nn::params::*};
+use crate::{
+    assists::DriverAssists, brakes::Brakes, car::*, config::*, drivetrain::Drivetrain,
+    nn::params::*, noise::ActuatorDelay, tyre::Tyre,
+};
 use bevy::prelude::*;
 use bevy_prototype_debug_lines::DebugLines;
 use bevy_rapier3d::prelude::*;
 use std::f32::consts::PI;
 
+// below this speed gas and brake select the direction of travel
+const STATIONARY_MPS: f32 = 0.5;
+
 pub fn esp_system(
-    mut query: Query<(Entity, &mut Car, &Velocity, &Transform), Changed<Car>>,
+    mut query: Query<
+        (
+            Entity,
+            &mut Car,
+            &Velocity,
+            &Transform,
+            Option<&mut ActuatorDelay>,
+            Option<&mut Drivetrain>,
+            &mut Brakes,
+            &mut DriverAssists,
+        ),
+        Changed<Car>,
+    >,
     mut wheel_set: ParamSet<(
         Query<
             (
@@ -14,6 +32,7 @@ pub fn esp_system(
                 &Transform,
                 &Velocity,
                 &mut ImpulseJoint,
+                Option<&Tyre>,
             ),
             With<WheelFront>,
         >,
@@ -24,6 +43,7 @@ pub fn esp_system(
                 &Transform,
                 &Velocity,
                 &mut ImpulseJoint,
+                Option<&Tyre>,
             ),
             With<WheelBack>,
         >,
@@ -33,44 +53,44 @@ pub fn esp_system(
     time: Res<Time>,
 ) {
     let d_seconds = time.delta_seconds();
-    let max_angle = PI / 4.;
+    let seconds = time.seconds_since_startup();
     let wheel_torque_ray_quat = Quat::from_axis_angle(-Vec3::Y, PI / 2.);
 
-    for (_entity, mut car, velocity, transform) in query.iter_mut() {
+    for (_entity, mut car, velocity, transform, delay, drivetrain, mut brakes, mut assists) in
+        query.iter_mut()
+    {
+        let (gas, brake, steering_input) = match delay {
+            Some(mut delay) => delay.push((car.gas, car.brake, car.steering)),
+            None => (car.gas, car.brake, car.steering),
+        };
         let car_vector = transform.rotation.mul_vec3(Vec3::Z);
         let delta = velocity.linvel.normalize() - car_vector.normalize();
         let car_angle_slip_rad = Vec3::new(delta.x, 0., delta.z).length();
         let moving_forward: bool = car_angle_slip_rad < PI / 2.;
-        let braking = match moving_forward {
-            true => car.brake > 0.,
-            false => car.gas > 0.,
-        };
         let car_mps = velocity.linvel.length();
         let car_kmh = car_mps / 1000. * 3600.;
-        let torque_speed_x: f32 = match braking {
-            true => 2.,
-            _ => match car_kmh / SPEED_LIMIT_KMH {
-                x if x >= 1. => 0.,
-                x => 1. - x,
-            },
-        };
-        let steering_speed_x: f32 = match car_kmh / STEERING_SPEEDLIMIT_KMH {
+        // nearly stopped the pedals pick the direction instead of braking
+        let stationary = car_mps < STATIONARY_MPS;
+        let braking = !stationary
+            && match moving_forward {
+                true => brake > 0.,
+                false => gas > 0.,
+            };
+        let steering_speed_x: f32 = match car_kmh / car.steering_speed_limit_kmh {
             x if x >= 1. => 0.,
             x => 1. - x,
         }
         .powi(2);
-        let pedal = if moving_forward {
-            if braking {
-                -car.brake
-            } else {
-                car.gas
-            }
-        } else {
-            if braking {
-                car.gas
-            } else {
-                -car.brake
-            }
+        let pedal = match (braking, stationary, moving_forward) {
+            (true, _, _) => 0.,
+            (false, true, _) => gas - brake,
+            (false, false, true) => gas,
+            (false, false, false) => -brake,
+        };
+        let brake_pedal = match (braking, moving_forward) {
+            (false, _) => 0.,
+            (true, true) => brake,
+            (true, false) => gas,
         };
         let dir = pedal.signum();
         let is_same_dir = car.prev_dir == dir;
@@ -78,7 +98,7 @@ pub fn esp_system(
         let prev_torque = if is_same_dir { car.prev_torque } else { 0. };
         let prev_steering = car.prev_steering;
         let (steering, mut torque) = (
-            prev_steering + (car.steering - prev_steering) * d_seconds * 4.,
+            prev_steering + (steering_input - prev_steering) * d_seconds * 4.,
             prev_torque + (car_torque - prev_torque) * d_seconds * 4.,
         );
         car.prev_steering = steering;
@@ -87,24 +107,99 @@ pub fn esp_system(
 
         torque = dir * torque;
 
-        let angle: f32 = max_angle * steering * (0.1 + 0.9 * steering_speed_x);
+        // spin in rad/s, positive when rolling forward, and longitudinal slip of every wheel
+        // +X is the car's left, the axle axis wheels spin around
+        let axle = transform.rotation.mul_vec3(Vec3::X);
+        let mut wheel_spin = [0.; 4];
+        let mut slip_ratio = [0.; 4];
+        let mut wheel_transforms = [*transform; 4];
+        for (i, wheel_entity) in car.wheels.iter().enumerate() {
+            let mut wheel_v: Option<(f32, Velocity)> = None;
+            if let Ok((wheel, _, t, v, _, _)) = wheel_set.p0().get(*wheel_entity) {
+                wheel_v = Some((wheel.radius, *v));
+                wheel_transforms[i] = *t;
+            }
+            if let Ok((wheel, _, t, v, _, _)) = wheel_set.p1().get(*wheel_entity) {
+                wheel_v = Some((wheel.radius, *v));
+                wheel_transforms[i] = *t;
+            }
+            if let Some((radius, v)) = wheel_v {
+                let v_long = v.linvel.dot(car_vector);
+                wheel_spin[i] = v.angvel.dot(axle);
+                slip_ratio[i] = (wheel_spin[i] * radius - v_long) / v_long.abs().max(1.);
+            }
+        }
+
+        // assists work on the measured state: front wheel heading, yaw rate and slip
+        let up = transform.rotation.mul_vec3(Vec3::Y);
+        let v_long = velocity.linvel.dot(car_vector);
+        let wheel_pos = wheel_transforms.map(|t| t.translation - transform.translation);
+        let wheelbase = (wheel_pos[0] + wheel_pos[1] - wheel_pos[2] - wheel_pos[3])
+            .dot(car_vector)
+            .abs()
+            / 2.;
+        let mut front_heading = wheel_transforms[0]
+            .rotation
+            .mul_vec3(Vec3::Y)
+            .cross(up)
+            .normalize_or_zero();
+        if front_heading.dot(car_vector) < 0. {
+            front_heading = -front_heading;
+        }
+        let steer_angle = car_vector
+            .cross(front_heading)
+            .dot(up)
+            .atan2(car_vector.dot(front_heading));
+        let brake_force = -car_vector * v_long.signum();
+        let brake_moment = wheel_pos.map(|p| p.cross(brake_force).dot(up));
+        let desired_yaw_rate = assists.desired_yaw_rate(v_long, steer_angle, wheelbase);
+        let esc_pressure =
+            assists.stability(velocity.angvel.dot(up), desired_yaw_rate, brake_moment);
+        let tc_factor = assists.traction(slip_ratio);
+
+        let brake_torque = brakes.update(
+            esc_pressure.map(|p| (brake_pedal + p).min(1.)),
+            car.handbrake,
+            wheel_spin,
+            slip_ratio,
+            d_seconds,
+        );
+
+        // with an engine the pedal torque is unused, drive comes from the drivetrain
+        let mut drive = [0.; 4];
+        if let Some(mut drivetrain) = drivetrain {
+            drive = drivetrain.update(pedal.abs(), pedal < 0., wheel_spin, seconds, d_seconds);
+            torque = 0.;
+        }
+
+        let angle: f32 = assists.steering_limit(
+            car.steering_lock * steering * (0.1 + 0.9 * steering_speed_x),
+            car_mps,
+            wheelbase,
+        );
         let quat = -Quat::from_axis_angle(Vec3::Y, -angle);
         let torque_vec = Vec3::new(0., torque, 0.);
         let steering_torque_vec = quat.mul_vec3(torque_vec);
 
-        for (_i, wheel_entity) in car.wheels.iter().enumerate() {
+        let mut wheel_slip = [0.; 4];
+        for (i, wheel_entity) in car.wheels.iter().enumerate() {
             let mut q_front_wheels = wheel_set.p0();
             let wheel_result = q_front_wheels.get_mut(*wheel_entity);
-            if let Ok((wheel, mut f, transform, v, mut j)) = wheel_result {
+            if let Ok((wheel, mut f, transform, v, mut j, tyre)) = wheel_result {
                 let radius_vel = v.angvel * wheel.radius;
                 let velocity_slip = (radius_vel[0] - v.linvel[2], radius_vel[2] + v.linvel[0]);
                 let slip_sq = (velocity_slip.0.powi(2) + velocity_slip.1.powi(2)).sqrt();
-                let max_slip = 10.;
-                let slip_sq_x: f32 = match slip_sq / max_slip {
-                    x if x >= 1. => 0.,
-                    x => 1. - x,
+                wheel_slip[i] = slip_sq;
+                // the tyre model limits traction itself
+                let slip_sq_x: f32 = match (tyre, slip_sq / MAX_WHEEL_SLIP) {
+                    (Some(_), _) => 1.,
+                    (None, x) if x >= 1. => 0.,
+                    (None, x) => 1. - x,
                 };
-                let total_torque = steering_torque_vec * slip_sq_x * torque_speed_x;
+                let total_torque = (steering_torque_vec + quat.mul_vec3(Vec3::Y * drive[i]))
+                    * slip_sq_x
+                    * tc_factor[i]
+                    + quat.mul_vec3(Vec3::Y * brake_torque[i]);
                 f.torque = (transform.rotation.mul_vec3(total_torque)).into();
 
                 if config.show_rays {
@@ -115,16 +210,21 @@ pub fn esp_system(
                 j.data.set_local_basis1(quat);
             }
 
-            if let Ok((wheel, mut f, transform, v, mut j)) = wheel_set.p1().get_mut(*wheel_entity) {
+            if let Ok((wheel, mut f, transform, v, mut j, tyre)) =
+                wheel_set.p1().get_mut(*wheel_entity)
+            {
                 let radius_vel = v.angvel * wheel.radius;
                 let velocity_slip = (radius_vel[0] - v.linvel[2], radius_vel[2] + v.linvel[0]);
                 let slip_sq = (velocity_slip.0.powi(2) + velocity_slip.1.powi(2)).sqrt();
-                let max_slip = 10.;
-                let slip_sq_x: f32 = match slip_sq / max_slip {
-                    x if x >= 1. => 0.,
-                    x => 1. - x,
+                wheel_slip[i] = slip_sq;
+                // the tyre model limits traction itself
+                let slip_sq_x: f32 = match (tyre, slip_sq / MAX_WHEEL_SLIP) {
+                    (Some(_), _) => 1.,
+                    (None, x) if x >= 1. => 0.,
+                    (None, x) => 1. - x,
                 };
-                let total_torque = torque_vec * slip_sq_x * torque_speed_x;
+                let total_torque = (torque_vec + Vec3::Y * drive[i]) * slip_sq_x * tc_factor[i]
+                    + Vec3::Y * brake_torque[i];
                 f.torque = (transform.rotation.mul_vec3(total_torque)).into();
 
                 if config.show_rays {
@@ -136,5 +236,6 @@ pub fn esp_system(
                 j.data.set_local_basis1(quat_back);
             }
         }
+        car.wheel_slip = wheel_slip;
     }
 }
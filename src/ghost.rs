@@ -0,0 +1,207 @@
+use crate::{car::*, config::Config};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+pub const GHOST_DIR: &str = "ghosts";
+const GHOST_SAMPLE_SECONDS: f32 = 0.1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GhostSample {
+    // since the lap started
+    pub seconds: f32,
+    pub meters: f32,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GhostLap {
+    pub track: String,
+    pub vehicle: usize,
+    pub lap_time: f32,
+    pub samples: Vec<GhostSample>,
+}
+
+impl GhostLap {
+    pub fn path(track: &str) -> String {
+        format!("{GHOST_DIR}/{track}.json")
+    }
+    // no ghost yet for a track that was never lapped
+    pub fn load(track: &str) -> Option<Self> {
+        let reader = BufReader::new(File::open(GhostLap::path(track)).ok()?);
+        let lap: GhostLap = serde_json::from_reader(reader).unwrap();
+        println!("ghost: {} lap {:.2}s", lap.track, lap.lap_time);
+        Some(lap)
+    }
+    pub fn save(&self) {
+        std::fs::create_dir_all(GHOST_DIR).unwrap();
+        let writer = BufWriter::new(File::create(GhostLap::path(&self.track)).unwrap());
+        serde_json::to_writer(writer, self).unwrap();
+    }
+    // seconds the ghost needed to reach the polyline meters of this lap
+    pub fn seconds_at_meters(&self, meters: f32) -> Option<f32> {
+        let i = self.samples.partition_point(|s| s.meters <= meters);
+        let (a, b) = (self.samples.get(i.checked_sub(1)?)?, self.samples.get(i)?);
+        let x = (meters - a.meters) / (b.meters - a.meters).max(f32::EPSILON);
+        Some(a.seconds + (b.seconds - a.seconds) * x)
+    }
+    pub fn pose_at(&self, seconds: f32) -> Option<Transform> {
+        let i = self.samples.partition_point(|s| s.seconds <= seconds);
+        let a = self.samples.get(i.checked_sub(1)?)?;
+        let b = self.samples.get(i).unwrap_or(a);
+        let x = ((seconds - a.seconds) / (b.seconds - a.seconds).max(f32::EPSILON)).clamp(0., 1.);
+        let (ta, tb) = (Vec3::from(a.translation), Vec3::from(b.translation));
+        let (ra, rb) = (Quat::from_array(a.rotation), Quat::from_array(b.rotation));
+        Some(Transform::from_translation(ta.lerp(tb, x)).with_rotation(ra.slerp(rb, x)))
+    }
+}
+
+#[derive(Component)]
+pub struct GhostCar;
+
+#[derive(Default)]
+pub struct Ghost {
+    pub best: Option<GhostLap>,
+    // live delta to the ghost at the same polyline meters, negative is ahead
+    pub delta: Option<f32>,
+    car: Option<Entity>,
+    lap: usize,
+    from_lap_start: bool,
+    current: Vec<GhostSample>,
+    entity: Option<(Entity, usize)>,
+    material: Option<Handle<StandardMaterial>>,
+}
+
+pub fn ghost_start_system(config: Res<Config>, mut ghost: ResMut<Ghost>) {
+    ghost.best = GhostLap::load(&config.track_name);
+}
+
+pub fn ghost_system(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    time: Res<Time>,
+    config: Res<Config>,
+    mut ghost: ResMut<Ghost>,
+    q_car: Query<(Entity, &Car, &Transform), With<HID>>,
+    mut q_ghost: Query<(&mut Transform, &mut Visibility), (With<GhostCar>, Without<Car>)>,
+    mut q_material: Query<(Entity, &mut Handle<StandardMaterial>), Added<Handle<StandardMaterial>>>,
+    q_parent: Query<&Parent>,
+) {
+    // every new material under the ghost turns translucent, scenes spawn their meshes later,
+    // so this runs before the early returns to not miss an Added frame
+    if let Some((ghost_entity, _)) = ghost.entity {
+        for (e, mut material) in q_material.iter_mut() {
+            let mut ancestor = e;
+            while let Ok(parent) = q_parent.get(ancestor) {
+                ancestor = parent.get();
+            }
+            if ancestor != ghost_entity {
+                continue;
+            }
+            let ghost_material = ghost.material.get_or_insert_with(|| {
+                materials.add(StandardMaterial {
+                    base_color: Color::rgba(0.3, 0.6, 1., 0.3),
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    ..default()
+                })
+            });
+            *material = ghost_material.clone();
+        }
+    }
+    let seconds = time.seconds_since_startup();
+    let (e, car, t) = match q_car.get_single() {
+        Ok(car) => car,
+        Err(_) => {
+            ghost.delta = None;
+            return;
+        }
+    };
+    // a respawned car starts over without a lap in progress
+    if ghost.car != Some(e) {
+        ghost.car = Some(e);
+        ghost.lap = car.lap;
+        ghost.from_lap_start = false;
+        ghost.current.clear();
+    }
+    if car.lap != ghost.lap {
+        if let (true, Some(lap_time)) = (ghost.from_lap_start, car.lap_time) {
+            if ghost.best.as_ref().map_or(true, |b| lap_time < b.lap_time) {
+                let best = GhostLap {
+                    track: config.track_name.clone(),
+                    vehicle: car.vehicle,
+                    lap_time,
+                    samples: std::mem::take(&mut ghost.current),
+                };
+                best.save();
+                println!("ghost: new best lap {lap_time:.2}s");
+                ghost.best = Some(best);
+            }
+        }
+        ghost.lap = car.lap;
+        ghost.from_lap_start = true;
+        ghost.current.clear();
+    }
+
+    let elapsed = car
+        .lap_started_at
+        .map(|started_at| (seconds - started_at) as f32);
+    if let Some(elapsed) = elapsed {
+        let due = ghost
+            .current
+            .last()
+            .map_or(true, |s| elapsed - s.seconds >= GHOST_SAMPLE_SECONDS);
+        if due {
+            ghost.current.push(GhostSample {
+                seconds: elapsed,
+                meters: car.meters,
+                translation: t.translation.to_array(),
+                rotation: t.rotation.to_array(),
+            });
+        }
+    }
+
+    let best = match &ghost.best {
+        Some(best) => best,
+        None => return,
+    };
+    let delta = elapsed.and_then(|elapsed| {
+        best.seconds_at_meters(car.meters)
+            .map(|ghost_seconds| elapsed - ghost_seconds)
+    });
+    let pose = elapsed.and_then(|elapsed| best.pose_at(elapsed));
+    let vehicle = best.vehicle;
+
+    // transforms only, the vehicle model without collider
+    if ghost.entity.map_or(true, |(_, v)| v != vehicle) {
+        if let Some((entity, _)) = ghost.entity {
+            commands.entity(entity).despawn_recursive();
+        }
+        let model = config.vehicle_models[vehicle % config.vehicle_models.len()].clone();
+        let model_transform = config.vehicles[vehicle % config.vehicles.len()]
+            .model
+            .transform();
+        let entity = commands
+            .spawn()
+            .insert(Name::new("ghost"))
+            .insert(GhostCar)
+            .insert_bundle(SpatialBundle {
+                visibility: Visibility { is_visible: false },
+                ..default()
+            })
+            .with_children(|children| {
+                model.spawn(children, model_transform);
+            })
+            .id();
+        ghost.entity = Some((entity, vehicle));
+    }
+    ghost.delta = delta;
+    if let Ok((mut ghost_t, mut visibility)) = q_ghost.get_single_mut() {
+        visibility.is_visible = pose.is_some();
+        if let Some(pose) = pose {
+            *ghost_t = pose;
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{
+    car::*,
+    config::Config,
+    nn::dqn_bevy::{CarDqnPrev, DqnResource},
+};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+pub const HEATMAP_DIR: &str = "heatmaps";
+const BIN_METERS: f32 = 10.;
+const RIBBON_HALF_WIDTH: f32 = 1.5;
+const RIBBON_LIFT: f32 = 0.1;
+const RIBBON_UPDATE_SECONDS: f64 = 1.;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeatmapBin {
+    pub speed_samples: usize,
+    pub speed_sum: f32,
+    pub reward_samples: usize,
+    pub reward_sum: f32,
+    pub crashes: usize,
+}
+
+impl HeatmapBin {
+    pub fn speed_kmh(&self) -> Option<f32> {
+        (self.speed_samples > 0).then(|| self.speed_sum / self.speed_samples as f32)
+    }
+    pub fn reward(&self) -> Option<f32> {
+        (self.reward_samples > 0).then(|| self.reward_sum / self.reward_samples as f32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeatmapMode {
+    Off,
+    Speed,
+    Reward,
+    Crashes,
+}
+
+// accumulators per BIN_METERS of car meters from the start line, kept per track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackHeatmap {
+    pub track: String,
+    pub bin_meters: f32,
+    pub bins: Vec<HeatmapBin>,
+}
+
+impl TrackHeatmap {
+    pub fn path(track: &str, extension: &str) -> String {
+        format!("{HEATMAP_DIR}/{track}.{extension}")
+    }
+    pub fn load(track: &str) -> Option<Self> {
+        let reader = BufReader::new(File::open(TrackHeatmap::path(track, "json")).ok()?);
+        let heatmap: TrackHeatmap = serde_json::from_reader(reader).unwrap();
+        println!("heatmap: {} bins: {}", heatmap.track, heatmap.bins.len());
+        Some(heatmap)
+    }
+    pub fn save(&self) {
+        std::fs::create_dir_all(HEATMAP_DIR).unwrap();
+        let writer = BufWriter::new(File::create(TrackHeatmap::path(&self.track, "json")).unwrap());
+        serde_json::to_writer(writer, self).unwrap();
+    }
+    // one row per bin for offline analysis
+    pub fn export_csv(&self) -> String {
+        std::fs::create_dir_all(HEATMAP_DIR).unwrap();
+        let path = TrackHeatmap::path(&self.track, "csv");
+        let mut writer = BufWriter::new(File::create(&path).unwrap());
+        writeln!(
+            writer,
+            "from_m,to_m,speed_samples,avg_speed_kmh,avg_reward,crashes"
+        )
+        .unwrap();
+        let cell = |v: Option<f32>| v.map_or("".to_string(), |v| format!("{v:.3}"));
+        for (i, bin) in self.bins.iter().enumerate() {
+            writeln!(
+                writer,
+                "{:.0},{:.0},{},{},{},{}",
+                i as f32 * self.bin_meters,
+                (i + 1) as f32 * self.bin_meters,
+                bin.speed_samples,
+                cell(bin.speed_kmh()),
+                cell(bin.reward()),
+                bin.crashes
+            )
+            .unwrap();
+        }
+        path
+    }
+    pub fn bin_mut(&mut self, meters: f32, track_length: f32) -> &mut HeatmapBin {
+        let i = (meters.rem_euclid(track_length) / self.bin_meters) as usize;
+        let i = i.min(self.bins.len() - 1);
+        &mut self.bins[i]
+    }
+    fn value(bin: &HeatmapBin, mode: HeatmapMode) -> Option<f32> {
+        match mode {
+            HeatmapMode::Speed => bin.speed_kmh(),
+            HeatmapMode::Reward => bin.reward(),
+            HeatmapMode::Crashes => Some(bin.crashes as f32),
+            HeatmapMode::Off => None,
+        }
+    }
+    // (min, max) over the bins with data, None without any
+    pub fn range(&self, mode: HeatmapMode) -> Option<(f32, f32)> {
+        self.bins
+            .iter()
+            .filter_map(|bin| TrackHeatmap::value(bin, mode))
+            .fold(None, |range, v| match range {
+                Some((min, max)) => Some((v.min(min), v.max(max))),
+                None => Some((v, v)),
+            })
+    }
+    // 0..1 of the bin under the mode within range, None without data
+    pub fn level(&self, mode: HeatmapMode, i: usize, range: (f32, f32)) -> Option<f32> {
+        let (min, max) = range;
+        let x =
+            (TrackHeatmap::value(self.bins.get(i)?, mode)? - min) / (max - min).max(f32::EPSILON);
+        // many crashes is bad, high speed and reward are good
+        match mode {
+            HeatmapMode::Crashes => Some(1. - x),
+            _ => Some(x),
+        }
+    }
+}
+
+pub struct Heatmap {
+    pub map: Option<TrackHeatmap>,
+    pub mode: HeatmapMode,
+    ribbon: Option<Handle<Mesh>>,
+    ribbon_updated_at: f64,
+    last_step: usize,
+    crashed: HashSet<usize>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self {
+            map: None,
+            mode: HeatmapMode::Off,
+            ribbon: None,
+            ribbon_updated_at: 0.,
+            last_step: 0,
+            crashed: HashSet::new(),
+        }
+    }
+}
+
+// red for the worst bins through yellow to green for the best, grey without data
+fn level_color(level: Option<f32>) -> [f32; 4] {
+    match level {
+        Some(x) => [(2. * (1. - x)).min(1.), (2. * x).min(1.), 0.1, 0.8],
+        None => [0.5, 0.5, 0.5, 0.3],
+    }
+}
+
+// a strip along the centerline, one pair of vertices per polyline vertex
+fn ribbon_mesh(config: &Config) -> Mesh {
+    let vertices = config.polyline.as_ref().unwrap().vertices();
+    let mut positions: Vec<[f32; 3]> = vec![];
+    let mut normals: Vec<[f32; 3]> = vec![];
+    for (i, v) in vertices.iter().enumerate() {
+        let p = Vec3::from(*v);
+        let next = Vec3::from(vertices[(i + 1).min(vertices.len() - 1)]);
+        let prev = Vec3::from(vertices[i.saturating_sub(1)]);
+        let mut dir = next - prev;
+        dir.y = 0.;
+        let side = Vec3::Y.cross(dir).normalize_or_zero() * RIBBON_HALF_WIDTH;
+        let p = p + Vec3::Y * RIBBON_LIFT;
+        positions.push((p + side).into());
+        positions.push((p - side).into());
+        normals.push([0., 1., 0.]);
+        normals.push([0., 1., 0.]);
+    }
+    let mut indices: Vec<u32> = vec![];
+    for i in 0..vertices.len().saturating_sub(1) as u32 {
+        let (a, b, c, d) = (2 * i, 2 * i + 1, 2 * i + 2, 2 * i + 3);
+        indices.extend([a, b, c, c, b, d]);
+    }
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_COLOR,
+        ribbon_colors(config, None, HeatmapMode::Off),
+    );
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+fn ribbon_colors(config: &Config, map: Option<&TrackHeatmap>, mode: HeatmapMode) -> Vec<[f32; 4]> {
+    let count = config.polyline.as_ref().unwrap().vertices().len();
+    let mut colors = Vec::with_capacity(count * 2);
+    // once per update, not per vertex
+    let range = map.and_then(|map| map.range(mode));
+    for i in 0..count {
+        let level = map.zip(range).and_then(|(map, range)| {
+            let polyline_meters = config.meters.get(i).copied().unwrap_or(config.track_length);
+            let meters = (polyline_meters - config.meters_shift).rem_euclid(config.track_length);
+            map.level(mode, (meters / map.bin_meters) as usize, range)
+        });
+        let color = level_color(level);
+        colors.push(color);
+        colors.push(color);
+    }
+    colors
+}
+
+pub fn heatmap_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    config: Res<Config>,
+    dqn: Res<DqnResource>,
+    mut heatmap: ResMut<Heatmap>,
+    q_car: Query<(&Car, &Velocity, &Children, Option<&CarDqnPrev>)>,
+    q_colliding_entities: Query<&CollidingEntities>,
+    q_name: Query<&Name>,
+) {
+    if config.track_length <= 0. {
+        return;
+    }
+    if heatmap.map.is_none() {
+        let bins = (config.track_length / BIN_METERS).ceil() as usize;
+        heatmap.map = Some(match TrackHeatmap::load(&config.track_name) {
+            Some(map) if map.bins.len() == bins => map,
+            _ => TrackHeatmap {
+                track: config.track_name.clone(),
+                bin_meters: BIN_METERS,
+                bins: vec![HeatmapBin::default(); bins],
+            },
+        });
+    }
+
+    // rewards are sampled once per network step
+    let new_step = dqn.step != heatmap.last_step;
+    heatmap.last_step = dqn.step;
+    let mut crashed: HashSet<usize> = HashSet::new();
+    let map = heatmap.map.as_mut().unwrap();
+    for (car, v, children, dqn_prev) in q_car.iter() {
+        let bin = map.bin_mut(car.meters, config.track_length);
+        bin.speed_samples += 1;
+        bin.speed_sum += v.linvel.length() * 3.6;
+        if let (true, Some(dqn_prev)) = (new_step, dqn_prev) {
+            bin.reward_samples += 1;
+            bin.reward_sum += dqn_prev.prev_reward;
+        }
+        if is_crashing(children, &q_colliding_entities, &q_name) {
+            crashed.insert(car.index);
+        }
+    }
+    for (car, ..) in q_car.iter() {
+        if crashed.contains(&car.index) && !heatmap.crashed.contains(&car.index) {
+            let map = heatmap.map.as_mut().unwrap();
+            map.bin_mut(car.meters, config.track_length).crashes += 1;
+        }
+    }
+    heatmap.crashed = crashed;
+
+    if input.just_pressed(KeyCode::F9) {
+        let map = heatmap.map.as_ref().unwrap();
+        map.save();
+        println!("heatmap exported to {}", map.export_csv());
+    }
+    if input.just_pressed(KeyCode::F10) {
+        heatmap.mode = match heatmap.mode {
+            HeatmapMode::Off => HeatmapMode::Speed,
+            HeatmapMode::Speed => HeatmapMode::Reward,
+            HeatmapMode::Reward => HeatmapMode::Crashes,
+            HeatmapMode::Crashes => HeatmapMode::Off,
+        };
+        println!("heatmap: {:?}", heatmap.mode);
+        heatmap.ribbon_updated_at = 0.;
+    }
+
+    let seconds = time.seconds_since_startup();
+    if heatmap.ribbon.is_none() && heatmap.mode != HeatmapMode::Off {
+        let mesh = meshes.add(ribbon_mesh(&config));
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: mesh.clone(),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::WHITE,
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    ..default()
+                }),
+                ..default()
+            })
+            .insert(Name::new("heatmap"));
+        heatmap.ribbon = Some(mesh);
+    }
+    if seconds - heatmap.ribbon_updated_at < RIBBON_UPDATE_SECONDS {
+        return;
+    }
+    heatmap.ribbon_updated_at = seconds;
+    if let Some(mesh) = heatmap.ribbon.as_ref().and_then(|h| meshes.get_mut(h)) {
+        // fully transparent when off, the mesh stays for the next mode
+        let mut colors = ribbon_colors(&config, heatmap.map.as_ref(), heatmap.mode);
+        if heatmap.mode == HeatmapMode::Off {
+            colors.iter_mut().for_each(|c| c[3] = 0.);
+        }
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    }
+}
@@ -1,9 +1,15 @@
 use crate::{
+    assists::DriverAssists,
     car::*,
     config::*,
     db::rb,
     db_client::DbClientResource,
-    nn::{dqn::OBSERVATION_ZERO, dqn_bevy::DqnResource},
+    drivetrain::Drivetrain,
+    nn::{
+        checkpoint::*,
+        dqn_bevy::{CarsDqnResource, DqnResource},
+        replay::decode_observation,
+    },
 };
 use bevy::prelude::*;
 use bevy_rapier3d::render::DebugRenderContext;
@@ -11,45 +17,77 @@ use bevy_rapier3d::render::DebugRenderContext;
 #[tokio::main]
 pub async fn keyboard_input_system(
     input: Res<Input<KeyCode>>,
+    time: Res<Time>,
     mut config: ResMut<Config>,
-    mut cars: Query<(&mut Car, &Transform, With<HID>)>,
+    mut cars: Query<(
+        &mut Car,
+        &Transform,
+        Option<&mut Drivetrain>,
+        &mut DriverAssists,
+        With<HID>,
+    )>,
     mut commands: Commands,
     q_car: Query<Entity, With<Car>>,
     q_wheel: Query<Entity, With<Wheel>>,
     mut debug_ctx: ResMut<DebugRenderContext>,
     dbres: Res<DbClientResource>,
     mut dqn: ResMut<DqnResource>,
+    mut cars_dqn: NonSendMut<CarsDqnResource>,
 ) {
     if input.just_pressed(KeyCode::B) {
         let rb: Vec<rb::Data> = dbres.client.rb().find_many(vec![]).exec().await.unwrap();
 
+        let (mut loaded, mut skipped) = (0, 0);
+        let mut skip_reason = String::new();
         for r in rb.iter() {
-            let mut state = OBSERVATION_ZERO;
-            let mut next_state = OBSERVATION_ZERO;
-            for (i, state_item) in r
-                .state
-                .split(",")
-                .map(|x| x.parse::<f32>().unwrap())
-                .enumerate()
-            {
-                state[i] = state_item;
+            match (
+                decode_observation(&r.state),
+                decode_observation(&r.next_state),
+            ) {
+                (Ok(state), Ok(next_state)) => {
+                    dqn.rb.store(
+                        state,
+                        r.action as usize,
+                        r.reward as f32,
+                        next_state,
+                        r.done,
+                    );
+                    loaded += 1;
+                }
+                (Err(err), _) | (_, Err(err)) => {
+                    skipped += 1;
+                    skip_reason = err;
+                }
             }
-            for (i, next_state_item) in r
-                .state
-                .split(",")
-                .map(|x| x.parse::<f32>().unwrap())
-                .enumerate()
-            {
-                next_state[i] = next_state_item;
-            }
-            dqn.rb.store(
-                state,
-                r.action as usize,
-                r.reward as f32,
-                next_state,
-                r.done,
-            );
         }
+        println!("replay buffer: loaded {loaded} rows, skipped {skipped}");
+        if skipped > 0 {
+            println!("replay buffer: last skipped row: {skip_reason}");
+        }
+    }
+    if input.just_pressed(KeyCode::K) {
+        match save_checkpoint(&cars_dqn, &dqn.normalizer) {
+            Ok(()) => println!("checkpoint saved to {CHECKPOINT_DIR}"),
+            Err(err) => println!("checkpoint save failed: {err}"),
+        }
+    }
+    if input.just_pressed(KeyCode::L) {
+        match load_checkpoint(&mut cars_dqn, &mut dqn.normalizer) {
+            Ok(()) => println!("checkpoint loaded from {CHECKPOINT_DIR}"),
+            Err(err) => println!("checkpoint load failed: {err}"),
+        }
+    }
+    if input.just_pressed(KeyCode::P) {
+        config.export_perception = !config.export_perception;
+        println!("perception export: {}", config.export_perception);
+    }
+    if input.just_pressed(KeyCode::F7) {
+        config.telemetry = !config.telemetry;
+        println!("telemetry: {}", config.telemetry);
+    }
+    if input.just_pressed(KeyCode::F8) {
+        config.nn_overlays = !config.nn_overlays;
+        println!("network overlays: {}", config.nn_overlays);
     }
     if input.just_pressed(KeyCode::N) {
         config.use_brain = !config.use_brain;
@@ -65,7 +103,32 @@ pub async fn keyboard_input_system(
     if input.just_pressed(KeyCode::R) {
         debug_ctx.enabled = !debug_ctx.enabled;
     }
-    for (mut car, _transform, _car) in cars.iter_mut() {
+    let seconds = time.seconds_since_startup();
+    for (mut car, _transform, drivetrain, mut assists, _car) in cars.iter_mut() {
+        if input.just_pressed(KeyCode::F1) {
+            assists.traction_control = !assists.traction_control;
+            println!("traction control: {}", assists.traction_control);
+        }
+        if input.just_pressed(KeyCode::F2) {
+            assists.stability_control = !assists.stability_control;
+            println!("stability control: {}", assists.stability_control);
+        }
+        if input.just_pressed(KeyCode::F3) {
+            assists.steering_assist = !assists.steering_assist;
+            println!("steering assist: {}", assists.steering_assist);
+        }
+        if let Some(mut drivetrain) = drivetrain {
+            if input.just_pressed(KeyCode::G) {
+                drivetrain.automatic = !drivetrain.automatic;
+                println!("gearbox automatic: {}", drivetrain.automatic);
+            }
+            if input.just_pressed(KeyCode::X) {
+                drivetrain.shift_up(seconds);
+            }
+            if input.just_pressed(KeyCode::Z) {
+                drivetrain.shift_down(seconds);
+            }
+        }
         if input.pressed(KeyCode::Up) {
             car.gas = 1.;
         }
@@ -73,6 +136,13 @@ pub async fn keyboard_input_system(
             car.gas = 0.;
         }
 
+        if input.pressed(KeyCode::H) {
+            car.handbrake = 1.;
+        }
+        if input.just_released(KeyCode::H) {
+            car.handbrake = 0.;
+        }
+
         if input.pressed(KeyCode::Down) {
             car.brake = 1.;
         }
@@ -1,5 +1,8 @@
 use bevy::prelude::*;
 
+#[derive(Component)]
+pub struct Sun;
+
 pub fn light_start_system(mut commands: Commands) {
     // commands.spawn_bundle(PointLightBundle {
     //     transform: Transform::from_xyz(0., 200., 0.),
@@ -15,26 +18,28 @@ pub fn light_start_system(mut commands: Commands) {
     // });
 
     const HSIZE: f32 = 200.;
-    commands.spawn_bundle(DirectionalLightBundle {
-        directional_light: DirectionalLight {
-            illuminance: 100_000.,
-            shadow_projection: OrthographicProjection {
-                left: -HSIZE,
-                right: HSIZE,
-                bottom: -HSIZE,
-                top: HSIZE,
-                near: -HSIZE,
-                far: HSIZE,
+    commands
+        .spawn_bundle(DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance: 100_000.,
+                shadow_projection: OrthographicProjection {
+                    left: -HSIZE,
+                    right: HSIZE,
+                    bottom: -HSIZE,
+                    top: HSIZE,
+                    near: -HSIZE,
+                    far: HSIZE,
+                    ..default()
+                },
+                shadows_enabled: false,
+                ..default()
+            },
+            transform: Transform {
+                translation: Vec3::new(0., 5., 0.),
+                rotation: Quat::from_rotation_x(-std::f32::consts::FRAC_PI_8),
                 ..default()
             },
-            shadows_enabled: false,
-            ..default()
-        },
-        transform: Transform {
-            translation: Vec3::new(0., 5., 0.),
-            rotation: Quat::from_rotation_x(-std::f32::consts::FRAC_PI_8),
             ..default()
-        },
-        ..default()
-    });
+        })
+        .insert(Sun);
 }
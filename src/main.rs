@@ -1,19 +1,39 @@
+mod aero;
+mod assists;
+mod brakes;
 mod camera;
 mod car;
 mod config;
 mod dash;
+mod dashboard;
 mod db;
 mod db_client;
+mod drivetrain;
 mod esp;
 mod gamepad;
+mod ghost;
+mod heatmap;
 mod input;
 mod light;
 mod mesh;
 mod nn;
+mod noise;
 mod progress;
+mod randomization;
+mod replay;
+mod sensor;
+mod surface;
+mod suspension;
+mod telemetry;
+mod terrain;
 mod track;
+mod tyre;
+mod vehicle;
+mod weather;
+use aero::*;
 use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*};
 use bevy_atmosphere::prelude::*;
+use bevy_egui::EguiPlugin;
 use bevy_framepace::{FramepacePlugin, FramepaceSettings, Limiter};
 use bevy_prototype_debug_lines::DebugLinesPlugin;
 use bevy_rapier3d::prelude::*;
@@ -21,14 +41,27 @@ use camera::*;
 use car::*;
 use config::*;
 use dash::*;
+use dashboard::*;
 use db_client::DbClientResource;
 use esp::*;
 use gamepad::*;
+use ghost::*;
+use heatmap::*;
 use input::*;
 use light::*;
-use nn::{dqn::dqn_system, dqn_bevy::*};
+use nn::{dqn::dqn_system, dqn_bevy::*, gradient::q_value_overlay_system};
+use noise::SimRng;
 use progress::*;
+use randomization::*;
+use replay::*;
+use sensor::*;
+use surface::*;
+use suspension::*;
+use telemetry::*;
+use terrain::Terrain;
 use track::*;
+use tyre::*;
+use weather::*;
 
 fn rapier_config_start_system(mut c: ResMut<RapierContext>) {
     c.integration_parameters.max_velocity_iterations = 512;
@@ -38,73 +71,121 @@ fn rapier_config_start_system(mut c: ResMut<RapierContext>) {
 
 const FPS: f32 = 60.;
 fn main() {
-    App::new()
-        .insert_resource(RapierConfiguration {
-            timestep_mode: TimestepMode::Fixed {
-                dt: 1. / FPS,
-                substeps: 10,
-            },
+    let mut config = Config::default();
+    // --playback <file> re-renders a recorded session without physics or the network
+    let replay = std::env::args()
+        .skip_while(|arg| arg != "--playback")
+        .nth(1)
+        .map(|path| Replay::load(&path));
+    if let Some(replay) = &replay {
+        config.terrain_path = replay.header.terrain_path.clone();
+        config.weather_path = replay.header.weather_path.clone();
+    }
+    let terrain = Terrain::load(&config.terrain_path);
+    let mut app = App::new();
+    app.insert_resource(RapierConfiguration {
+        timestep_mode: TimestepMode::Fixed {
+            dt: 1. / FPS,
+            substeps: 10,
+        },
+        physics_pipeline_active: replay.is_none(),
+        ..default()
+    })
+    .insert_resource(FramepaceSettings {
+        limiter: Limiter::from_framerate(FPS as f64),
+        ..default()
+    })
+    .insert_resource(DbClientResource::default())
+    .insert_resource(DqnResource::default())
+    .insert_resource(SimRng::default())
+    .insert_resource(WindowDescriptor {
+        title: "car sim deep learning".to_string(),
+        width: 1024.,
+        height: 768.,
+        ..default()
+    })
+    .insert_resource(Msaa { samples: 4 })
+    .insert_resource(config)
+    .insert_resource(terrain)
+    .insert_resource(CameraConfig::default())
+    .insert_resource(AtmosphereSettings { resolution: 1024 })
+    .insert_resource(Atmosphere::default())
+    .insert_resource(Weather::default())
+    .add_plugins(DefaultPlugins)
+    .add_plugin(AtmospherePlugin)
+    .add_plugin(FramepacePlugin)
+    .add_startup_system(camera_start_system)
+    .add_system(camera_controller_system)
+    .add_system(camera_switch_system)
+    .add_plugin(FrameTimeDiagnosticsPlugin::default())
+    .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+    .add_plugin(RapierDebugRenderPlugin {
+        style: DebugRenderStyle {
+            rigid_body_axes_length: 0.5,
+            subdivisions: 50,
             ..default()
-        })
-        .insert_resource(FramepaceSettings {
-            limiter: Limiter::from_framerate(FPS as f64),
-            ..default()
-        })
-        .insert_resource(DbClientResource::default())
-        .insert_resource(DqnResource::default())
-        .insert_resource(WindowDescriptor {
-            title: "car sim deep learning".to_string(),
-            width: 1024.,
-            height: 768.,
-            ..default()
-        })
-        .insert_resource(Msaa { samples: 4 })
-        .insert_resource(Config::default())
-        .insert_resource(CameraConfig::default())
-        .insert_resource(AtmosphereSettings { resolution: 1024 })
-        .add_plugins(DefaultPlugins)
-        .add_plugin(AtmospherePlugin)
-        .add_plugin(FramepacePlugin)
-        .add_startup_system(camera_start_system)
-        .add_system(camera_controller_system)
-        .add_system(camera_switch_system)
-        .add_plugin(FrameTimeDiagnosticsPlugin::default())
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_plugin(RapierDebugRenderPlugin {
-            style: DebugRenderStyle {
-                rigid_body_axes_length: 0.5,
-                subdivisions: 50,
-                ..default()
-            },
-            // | DebugRenderMode::COLLIDER_AABBS
-            mode: DebugRenderMode::COLLIDER_SHAPES
-                | DebugRenderMode::RIGID_BODY_AXES
-                | DebugRenderMode::JOINTS
-                | DebugRenderMode::CONTACTS
-                | DebugRenderMode::SOLVER_CONTACTS,
-            ..default()
-        })
-        .add_plugin(DebugLinesPlugin::with_depth_test(true))
-        .init_resource::<GamepadLobby>()
+        },
+        // | DebugRenderMode::COLLIDER_AABBS
+        mode: DebugRenderMode::COLLIDER_SHAPES
+            | DebugRenderMode::RIGID_BODY_AXES
+            | DebugRenderMode::JOINTS
+            | DebugRenderMode::CONTACTS
+            | DebugRenderMode::SOLVER_CONTACTS,
+        ..default()
+    })
+    .add_plugin(DebugLinesPlugin::with_depth_test(true))
+    .init_resource::<GamepadLobby>()
+    .add_startup_system(track_start_system)
+    .add_startup_system(track_decorations_start_system)
+    .add_startup_system(track_polyline_start_system)
+    .add_startup_system(light_start_system)
+    .add_startup_system(weather_start_system)
+    .add_startup_system(dash_fps_start_system)
+    .add_system(dash_fps_system);
+    if let Some(replay) = replay {
+        app.insert_resource(replay)
+            .add_startup_system(replay_start_system.after(track_polyline_start_system))
+            .add_system(replay_playback_system)
+            .add_system(weather_system.after(replay_playback_system));
+        app.run();
+        return;
+    }
+    app.add_system(weather_system)
+        .insert_resource(Recorder::default())
+        .insert_resource(Ghost::default())
+        .insert_resource(Telemetry::default())
+        .insert_resource(Heatmap::default())
+        .insert_resource(TrainingDashboard::default())
+        .add_plugin(EguiPlugin)
+        .add_startup_system(ghost_start_system)
         .add_startup_system(dqn_exclusive_start_system.exclusive_system())
-        .add_startup_system(track_start_system)
-        .add_startup_system(track_decorations_start_system)
-        .add_startup_system(track_polyline_start_system)
         .add_startup_system(car_start_system.after(track_polyline_start_system))
-        .add_startup_system(light_start_system)
         .add_startup_system(dash_speed_start_system)
-        .add_startup_system(dash_fps_start_system)
         .add_startup_system(rapier_config_start_system)
         .add_system(esp_system)
+        .add_system(impulse_reset_system)
+        .add_system(suspension_system.after(impulse_reset_system))
+        .add_system(aero_system)
+        .add_system(surface_system)
+        .add_system(tyre_system.after(suspension_system).after(surface_system))
+        // the sim rng is drawn from in this order
         .add_system(car_sensor_system)
-        .add_system(dqn_system)
+        .add_system(lidar_system.after(car_sensor_system))
+        .add_system(depth_camera_system.after(lidar_system))
+        .add_system(dqn_system.after(depth_camera_system))
         .add_system(dqn_dash_update_system)
-        .add_system(dash_fps_system)
+        .add_system(training_dashboard_system)
+        .add_system(q_value_overlay_system)
         .add_system(dash_leaderboard_system)
         .add_system(dash_speed_update_system)
         // .add_system(gamepad_input_system)
         .add_system(keyboard_input_system)
         .add_system(progress_system)
+        .add_system(randomization_track_system)
+        .add_system(replay_record_system.after(progress_system))
+        .add_system(ghost_system.after(progress_system))
+        .add_system(telemetry_system.after(progress_system))
+        .add_system(heatmap_system.after(progress_system))
         .add_system_to_stage(CoreStage::PreUpdate, gamepad_stage_preupdate_system)
         .run();
 }
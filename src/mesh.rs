@@ -6,6 +6,8 @@ use bevy::{
     },
 };
 use bevy_rapier3d::{na::Point3, prelude::Real};
+use std::fs::File;
+use std::io::BufReader;
 
 pub fn bevy_mesh(buffers: (Vec<Point3<Real>>, Vec<[u32; 3]>)) -> Mesh {
     let (vtx, idx) = buffers;
@@ -34,3 +36,24 @@ pub fn bevy_mesh(buffers: (Vec<Point3<Real>>, Vec<[u32; 3]>)) -> Mesh {
     mesh.set_indices(Some(Indices::U32(indices)));
     mesh
 }
+
+pub fn obj_mesh(path: &str) -> Mesh {
+    let input = BufReader::new(File::open(path).unwrap());
+    let model = obj::raw::parse_obj(input).unwrap();
+    let obj: obj::Obj<obj::TexturedVertex, u32> = obj::Obj::new(model).unwrap();
+
+    let positions: Vec<[f32; 3]> = obj.vertices.iter().map(|v| v.position).collect();
+    let normals: Vec<[f32; 3]> = obj.vertices.iter().map(|v| v.normal).collect();
+    let uvs: Vec<[f32; 2]> = obj
+        .vertices
+        .iter()
+        .map(|v| [v.texture[0], 1.0 - v.texture[1]])
+        .collect();
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(obj.indices)));
+    mesh
+}
@@ -0,0 +1,62 @@
+use super::{
+    dqn_bevy::*, normalizer::RunningNormalizer, observation::observation_layout, params::*,
+};
+use dfdx::prelude::*;
+use std::fs;
+
+pub const CHECKPOINT_DIR: &str = "checkpoints";
+const QN_FILE: &str = "qn.npz";
+const NORMALIZER_FILE: &str = "normalizer.json";
+const LAYOUT_FILE: &str = "layout.json";
+
+// the observation layout and sensor rig the network was trained with
+fn layout_json() -> serde_json::Value {
+    serde_json::json!({
+        "layout": observation_layout(),
+        "sensor_rig": SENSOR_RIG,
+        "sensor_count": SENSOR_COUNT,
+    })
+}
+
+pub fn save_checkpoint(
+    cars_dqn: &CarsDqnResource,
+    normalizer: &RunningNormalizer,
+) -> Result<(), String> {
+    fs::create_dir_all(CHECKPOINT_DIR).map_err(|e| e.to_string())?;
+    fs::write(
+        format!("{CHECKPOINT_DIR}/{LAYOUT_FILE}"),
+        serde_json::to_string_pretty(&layout_json()).unwrap(),
+    )
+    .map_err(|e| e.to_string())?;
+    cars_dqn
+        .qn
+        .save(format!("{CHECKPOINT_DIR}/{QN_FILE}"))
+        .map_err(|e| format!("{e:?}"))?;
+    normalizer.save(&format!("{CHECKPOINT_DIR}/{NORMALIZER_FILE}"))
+}
+
+pub fn load_checkpoint(
+    cars_dqn: &mut CarsDqnResource,
+    normalizer: &mut RunningNormalizer,
+) -> Result<(), String> {
+    let layout: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(format!("{CHECKPOINT_DIR}/{LAYOUT_FILE}"))
+            .map_err(|e| e.to_string())?,
+    )
+    .map_err(|e| e.to_string())?;
+    if layout != layout_json() {
+        return Err(format!(
+            "checkpoint was trained with {layout}, this build has {}",
+            layout_json()
+        ));
+    }
+    let loaded_normalizer =
+        RunningNormalizer::load(&format!("{CHECKPOINT_DIR}/{NORMALIZER_FILE}"))?;
+    cars_dqn
+        .qn
+        .load(format!("{CHECKPOINT_DIR}/{QN_FILE}"))
+        .map_err(|e| format!("{e:?}"))?;
+    cars_dqn.tqn = cars_dqn.qn.clone();
+    *normalizer = loaded_normalizer;
+    Ok(())
+}
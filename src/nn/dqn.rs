@@ -4,8 +4,11 @@ use crate::{
     car::*,
     config::*,
     db_client::DbClientResource,
-    nn::{dqn_bevy::*, util::*},
-    track::*,
+    nn::{dqn_bevy::*, gradient::saliency, observation::*, reward::*, util::*},
+    noise::SimRng,
+    randomization::PhysicsSample,
+    sensor::{DepthCamera, Lidar},
+    suspension::Suspension,
 };
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
@@ -14,12 +17,14 @@ use rand::Rng;
 use std::time::Instant;
 
 pub type QNetwork = (
-    (Linear<STATE_SIZE, HIDDEN_SIZE>, ReLU),
+    (Linear<OBSERVATION_SIZE, HIDDEN_SIZE>, ReLU),
     (Linear<HIDDEN_SIZE, HIDDEN_SIZE>, ReLU),
     Linear<HIDDEN_SIZE, ACTIONS>,
 );
-pub type Observation = [f32; STATE_SIZE];
-pub const OBSERVATION_ZERO: Observation = [0.; STATE_SIZE];
+pub type Frame = [f32; STATE_SIZE];
+pub const FRAME_ZERO: Frame = [0.; STATE_SIZE];
+pub type Observation = [f32; OBSERVATION_SIZE];
+pub const OBSERVATION_ZERO: Observation = [0.; OBSERVATION_SIZE];
 
 pub fn dqn_system(
     time: Res<Time>,
@@ -35,28 +40,35 @@ pub fn dqn_system(
         Entity,
         Option<&HID>,
         &mut CarDqnPrev,
+        &mut CarFrameStack,
+        Option<&Lidar>,
+        Option<&DepthCamera>,
+        &PhysicsSample,
+        Option<&Suspension>,
     )>,
-    q_colliding_entities: Query<&CollidingEntities, With<CollidingEntities>>,
+    q_colliding_entities: Query<&CollidingEntities>,
     mut config: ResMut<Config>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut camera_config: ResMut<CameraConfig>,
     dbres: Res<DbClientResource>,
+    mut rng: ResMut<SimRng>,
 ) {
     let seconds = time.seconds_since_startup();
     if dqn.respawn_at > 0. && seconds > dqn.respawn_at {
-        let (transform, init_meters) = config.get_transform_random();
+        let (transform, init_meters) = config.get_transform_random(&mut rng);
+        let sample = config.randomization.sample(&mut rng);
         let new_car_id = spawn_car(
             &mut commands,
             &mut meshes,
             &mut materials,
-            &config.car_scene.as_ref().unwrap(),
             dqn.respawn_is_hid,
             transform,
             dqn.respawn_index,
             init_meters,
-            config.max_torque,
+            &config,
+            sample,
         );
         // if camera_config.mode.not_none() && dqn.respawn_is_hid {
         camera_config.camera_follow = Some(new_car_id);
@@ -68,26 +80,32 @@ pub fn dqn_system(
         config.use_brain = true;
         return;
     };
+    if dqn.reward_fn.weights != config.reward_weights {
+        dqn.reward_fn = CompositeReward::from_weights(&config.reward_weights);
+    }
     let should_act: bool = seconds > dqn.seconds;
     if should_act {
         dqn.seconds = seconds + STEP_DURATION;
         dqn.step += 1;
     }
 
-    for (mut car, v, tr, children, e, hid, mut car_dqn_prev) in q_car.iter_mut() {
+    for (
+        mut car,
+        v,
+        tr,
+        children,
+        e,
+        hid,
+        mut car_dqn_prev,
+        mut frame_stack,
+        lidar,
+        depth_camera,
+        physics,
+        suspension,
+    ) in q_car.iter_mut()
+    {
         let is_hid = hid.is_some();
-        let mut crash: bool = false;
-        for &child in children.iter() {
-            let colliding_entities = q_colliding_entities.get(child);
-            if let Ok(colliding_entities) = colliding_entities {
-                for e in colliding_entities.iter() {
-                    let colliding_entity = q_name.get(e).unwrap();
-                    if !colliding_entity.contains(ASSET_ROAD) {
-                        crash = true;
-                    }
-                }
-            }
-        }
+        let crash = is_crashing(children, &q_colliding_entities, &q_name);
 
         let mut vel_angle = car.line_dir.angle_between(v.linvel);
         if vel_angle.is_nan() {
@@ -104,50 +122,90 @@ pub fn dqn_system(
         d_from_center.y = 0.;
         let d = d_from_center.length();
 
-        let shape_reward = || -> f32 {
-            if crash {
-                return -1.;
-            }
-            // https://team.inria.fr/rits/files/2018/02/ICRA18_EndToEndDriving_CameraReady.pdf
-            // In [13] the reward is computed as a function of the difference of angle α between the road and car’s heading and the speed v.
-            // R = v(cos α − d)
-            let mut reward = v.linvel.length() / SPEED_LIMIT_MPS * (vel_cos - d / 5.);
-            if vel_cos.is_sign_positive() && pos_cos.is_sign_negative() {
-                reward = -reward;
-            }
-            if reward.is_nan() {
-                return 0.;
-            }
-            return reward;
+        let mut meters_delta = car.meters - car_dqn_prev.prev_meters;
+        if meters_delta.abs() > config.track_length / 2. {
+            meters_delta -= config.track_length * meters_delta.signum();
+        }
+        let lap_time = match car.lap != car_dqn_prev.prev_lap {
+            true => car.lap_time,
+            false => None,
         };
-        let reward = shape_reward();
+        let (reward, reward_components) = dqn.reward_fn.evaluate(&RewardInput {
+            crash,
+            speed_mps: v.linvel.length(),
+            vel_cos,
+            pos_cos,
+            d_from_center: d,
+            meters_delta,
+            lap_time,
+            steering_delta: car.steering - car_dqn_prev.prev_steering,
+            wheels_off_track: car
+                .wheel_surface
+                .iter()
+                .filter(|s| s.is_off_track())
+                .count(),
+        });
         let mps = v.linvel.length();
         let kmh = mps / 1000. * 3600.;
-        let mut obs: Observation = [0.; STATE_SIZE];
-        for i in 0..obs.len() {
-            obs[i] = match i {
-                0 => kmh / 100.,
-                1 => vel_cos,
-                2 => pos_cos,
-                _ => car.sensor_inputs[i - STATE_SIZE_BASE],
-            };
+        let frame: Frame = build_observation(
+            &config,
+            &car,
+            tr.translation,
+            kmh,
+            vel_cos,
+            pos_cos,
+            v.angvel.y,
+            lidar,
+            depth_camera,
+            suspension,
+        );
+        if NORMALIZE_OBSERVATION && should_act {
+            dqn.normalizer.update(&frame);
         }
+        // raw frames are stored, the network sees them normalized with the current stats
+        let raw_obs: Observation = frame_stack.observation(&frame);
+        let obs = dqn.normalizer.normalize_observation(&raw_obs);
 
         let (prev_action, prev_obs) = (car_dqn_prev.prev_action, car_dqn_prev.prev_obs);
         if config.use_brain && (should_act || crash) && !prev_obs.iter().all(|&x| x == 0.) {
-            dqn.rb.store(prev_obs, prev_action, reward, obs, crash);
+            dqn.rb.store(prev_obs, prev_action, reward, raw_obs, crash);
             if dqn.rb.should_persist() {
                 dqn.rb.persist(&dbres.client);
             }
         }
 
-        let (action, exploration) = cars_dqn.act(obs, dqn.eps);
+        let (action, exploration, q_values) = cars_dqn.act(obs, dqn.eps, &mut rng);
+        car_dqn_prev.q_values = q_values;
         if should_act && !crash {
-            car_dqn_prev.prev_obs = obs;
+            // a traced backward pass, only for the action that is actually taken
+            if config.nn_overlays {
+                car_dqn_prev.saliency = saliency(&cars_dqn.qn, obs, action);
+            }
+            car_dqn_prev.prev_obs = raw_obs;
             car_dqn_prev.prev_action = action;
             car_dqn_prev.prev_reward = reward;
+            car_dqn_prev.prev_meters = car.meters;
+            car_dqn_prev.prev_lap = car.lap;
+            car_dqn_prev.prev_steering = car.steering;
+            car_dqn_prev.episode_return += reward;
+            car_dqn_prev.episode_steps += 1;
+            frame_stack.push(frame);
         }
         if crash {
+            let step = dqn.step;
+            dqn.metrics.episode(
+                step,
+                car_dqn_prev.episode_return + reward,
+                car_dqn_prev.episode_steps + 1,
+                physics,
+            );
+            dqn.metrics.crash(step, seconds);
+            println!(
+                "episode end car {}: {:.0}m physics {:?}",
+                car.index,
+                car.meters - car.init_meters,
+                physics
+            );
             dqn.crashes += 1;
             dqn.respawn_at = seconds + 0.5;
             dqn.respawn_is_hid = is_hid;
@@ -161,14 +219,15 @@ pub fn dqn_system(
         }
 
         if let Some(_hid) = hid {
-            if dqn.rb.len() < BATCH_SIZE {
+            log_reward_components(&reward_components);
+            if dqn.rb.len() < BATCH_SIZE || dqn.training_paused {
                 log_action_reward(car_dqn_prev.prev_action, reward);
             } else {
                 let start = Instant::now();
-                let mut rng = rand::thread_rng();
-                let batch_indexes = [(); BATCH_SIZE].map(|_| rng.gen_range(0..dqn.rb.len()));
-                let (s, a, r, sn, done) = dqn.rb.get_batch_tensors(batch_indexes);
+                let batch_indexes = [(); BATCH_SIZE].map(|_| rng.rng.gen_range(0..dqn.rb.len()));
+                let (s, a, r, sn, done) = dqn.rb.get_batch_tensors(batch_indexes, &dqn.normalizer);
                 let mut loss_string: String = String::from("");
+                let (mut loss_sum, mut mean_q, mut td_error) = (0., 0., 0.);
                 for _i_epoch in 0..EPOCHS {
                     let next_q_values: Tensor2D<BATCH_SIZE, ACTIONS> =
                         cars_dqn.tqn.forward(sn.clone());
@@ -177,9 +236,19 @@ pub fn dqn_system(
                     // forward through model, computing gradients
                     let q_values: Tensor2D<BATCH_SIZE, ACTIONS, OwnedTape> =
                         cars_dqn.qn.forward(s.trace());
+                    mean_q = q_values.data().iter().flatten().sum::<f32>()
+                        / (BATCH_SIZE * ACTIONS) as f32;
                     let action_qs: Tensor1D<BATCH_SIZE, OwnedTape> = q_values.select(&a);
+                    td_error = action_qs
+                        .data()
+                        .iter()
+                        .zip(target_q.data().iter())
+                        .map(|(q, target)| (target - q).abs())
+                        .sum::<f32>()
+                        / BATCH_SIZE as f32;
                     let loss = huber_loss(action_qs, &target_q, 1.);
                     let loss_v = *loss.data();
+                    loss_sum += loss_v;
                     // run backprop
                     let gradients = loss.backward();
                     sgd_res
@@ -191,6 +260,16 @@ pub fn dqn_system(
                     }
                 }
                 log_training(exploration, action, reward, &loss_string, start);
+                // mean q and td error of the last epoch
+                let step = dqn.step;
+                let (eps, rb_len) = (dqn.eps, dqn.rb.len());
+                let metrics = &mut dqn.metrics;
+                metrics.scalar("loss", step, loss_sum / EPOCHS as f32);
+                metrics.scalar("mean_q", step, mean_q);
+                metrics.scalar("td_error", step, td_error);
+                metrics.scalar("epsilon", step, eps);
+                metrics.scalar("replay_size", step, rb_len as f32);
+                metrics.flush();
                 if dqn.step % SYNC_INTERVAL_STEPS == 0 && dqn.rb.len() > BATCH_SIZE * 2 {
                     dbg!("networks sync");
                     cars_dqn.tqn = cars_dqn.qn.clone();
@@ -203,9 +282,9 @@ pub fn dqn_system(
             }
         }
 
-        let (gas, brake, left, right) = map_action_to_car(action);
+        let (gas, brake, steering) = map_action_to_car(action);
         car.gas = gas;
         car.brake = brake;
-        car.steering = -left + right;
+        car.steering = steering;
     }
 }
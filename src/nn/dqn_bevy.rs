@@ -1,41 +1,96 @@
-use super::{params::*, replay::ReplayBuffer};
-use crate::{dash::*, nn::dqn::*};
+use super::{
+    metrics::Metrics,
+    normalizer::RunningNormalizer,
+    params::*,
+    replay::ReplayBuffer,
+    reward::{CompositeReward, RewardWeights},
+};
+use crate::{dash::*, nn::dqn::*, noise::SimRng};
 use bevy::prelude::*;
 use dfdx::prelude::*;
 use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::VecDeque;
 
 #[derive(Component, Debug)]
 pub struct CarDqnPrev {
+    // raw, not normalized
     pub prev_obs: Observation,
     pub prev_action: usize,
     pub prev_reward: f32,
+    pub prev_meters: f32,
+    pub prev_lap: usize,
+    pub prev_steering: f32,
+    // of the latest observation, kept for telemetry
+    pub q_values: [f32; ACTIONS],
+    // per range sensor, only computed with the overlays on
+    pub saliency: [f32; SENSOR_COUNT],
+    pub episode_return: f32,
+    pub episode_steps: usize,
 }
 
 impl CarDqnPrev {
-    pub fn new() -> Self {
+    // progress of the first step is counted from the spawn point
+    pub fn new(init_meters: f32) -> Self {
         Self {
-            prev_obs: [0.; STATE_SIZE],
+            prev_obs: OBSERVATION_ZERO,
             prev_action: 0,
             prev_reward: 0.,
+            prev_meters: init_meters,
+            prev_lap: 0,
+            prev_steering: 0.,
+            q_values: [0.; ACTIONS],
+            saliency: [0.; SENSOR_COUNT],
+            episode_return: 0.,
+            episode_steps: 0,
         }
     }
 }
 
+// previous frames of a car, newest first, without the current one
+#[derive(Component, Debug)]
+pub struct CarFrameStack {
+    pub frames: VecDeque<Frame>,
+}
+
+impl CarFrameStack {
+    pub fn new() -> Self {
+        Self {
+            frames: VecDeque::from(vec![FRAME_ZERO; FRAME_STACK - 1]),
+        }
+    }
+    pub fn observation(&self, frame: &Frame) -> Observation {
+        let mut obs: Observation = OBSERVATION_ZERO;
+        for (i, f) in std::iter::once(frame).chain(self.frames.iter()).enumerate() {
+            obs[i * STATE_SIZE..(i + 1) * STATE_SIZE].copy_from_slice(f);
+        }
+        obs
+    }
+    pub fn push(&mut self, frame: Frame) {
+        self.frames.push_front(frame);
+        self.frames.truncate(FRAME_STACK - 1);
+    }
+}
+
 pub struct CarsDqnResource {
     pub qn: QNetwork,
     pub tqn: QNetwork,
 }
 impl CarsDqnResource {
-    pub fn act(&self, obs: Observation, epsilon: f32) -> (usize, bool) {
+    pub fn act(
+        &self,
+        obs: Observation,
+        epsilon: f32,
+        rng: &mut SimRng,
+    ) -> (usize, bool, [f32; ACTIONS]) {
         let obs_state_tensor = Tensor1D::new(obs);
-        let mut rng = rand::thread_rng();
+        let rng = &mut rng.rng;
         let random_number = rng.gen_range(0.0..1.0);
         let exploration = random_number < epsilon;
 
+        let q_values = self.qn.forward(obs_state_tensor.clone());
         let action: usize = if exploration {
-            rng.gen_range(0..ACTIONS - 1)
+            rng.gen_range(0..ACTIONS)
         } else {
-            let q_values = self.qn.forward(obs_state_tensor.clone());
             let max_q_value = *q_values.clone().max_axis::<-1>().data();
             let some_action = q_values
                 .clone()
@@ -49,10 +104,10 @@ impl CarsDqnResource {
                 some_action.unwrap()
             }
         };
-        (action, exploration)
+        (action, exploration, *q_values.data())
     }
     pub fn new() -> Self {
-        let mut rng = StdRng::seed_from_u64(0);
+        let mut rng = StdRng::seed_from_u64(SIM_SEED);
         let mut qn = QNetwork::default();
         qn.reset_params(&mut rng);
         Self {
@@ -67,10 +122,15 @@ pub struct DqnResource {
     pub step: usize,
     pub crashes: usize,
     pub rb: ReplayBuffer,
+    pub normalizer: RunningNormalizer,
+    pub reward_fn: CompositeReward,
     pub eps: f32,
     pub max_eps: f32,
     pub min_eps: f32,
     pub done: f32,
+    pub metrics: Metrics,
+    // acting and replay storing go on, only the network updates stop
+    pub training_paused: bool,
 
     pub respawn_at: f64,
     pub respawn_is_hid: bool,
@@ -83,10 +143,14 @@ impl DqnResource {
             step: 0,
             crashes: 0,
             rb: ReplayBuffer::new(),
+            normalizer: RunningNormalizer::new(),
+            reward_fn: CompositeReward::from_weights(&RewardWeights::default()),
             eps: 1.,
             max_eps: 1.,
             min_eps: 0.01,
             done: 0.,
+            metrics: Metrics::new(),
+            training_paused: false,
 
             respawn_at: 0.,
             respawn_is_hid: false,
@@ -1,28 +1,70 @@
-// use bevy::{prelude::*, render::mesh::VertexAttributeValues};
-// TODO gradient visualisation mesh
-pub fn gradient_vis_start_system(// mut commands: Commands,
-    // mut meshes: ResMut<Assets<Mesh>>,
-    // mut materials: ResMut<Assets<StandardMaterial>>,
+use super::{dqn::*, dqn_bevy::CarDqnPrev, params::*};
+use crate::{car::Car, config::Config};
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+use dfdx::prelude::*;
+
+const Q_BAR_HEIGHT: f32 = 1.5;
+const Q_BAR_SPACING: f32 = 0.12;
+const Q_BAR_LIFT: f32 = 1.2;
+
+// how much the chosen action's q-value moves with each range sensor of the newest frame, 0..1
+pub fn saliency(qn: &QNetwork, obs: Observation, action: usize) -> [f32; SENSOR_COUNT] {
+    let x: Tensor1D<OBSERVATION_SIZE> = Tensor1D::new(obs);
+    let q_values: Tensor1D<ACTIONS, OwnedTape> = qn.forward(x.trace());
+    let chosen: Tensor0D<OwnedTape> = q_values.select(&action);
+    let gradients = chosen.backward();
+    let gradient = gradients.ref_gradient(&x);
+    let mut sensors = [0.; SENSOR_COUNT];
+    for (s, g) in sensors
+        .iter_mut()
+        .zip(gradient[SENSOR_OFFSET..SENSOR_OFFSET + SENSOR_COUNT].iter())
+    {
+        *s = g.abs();
+    }
+    let max = sensors.iter().cloned().fold(0., f32::max);
+    if max > 0. {
+        sensors.iter_mut().for_each(|s| *s /= max);
+    }
+    sensors
+}
+
+// q-values per action as bars above every car, grouped by throttle level,
+// steering from left to right as seen from behind, the greedy action in green
+pub fn q_value_overlay_system(
+    config: Res<Config>,
+    q_car: Query<(&Transform, &Car, &CarDqnPrev)>,
+    mut lines: ResMut<DebugLines>,
 ) {
-    // let mut colorful_cube = Mesh::from(shape::Cube { size: 5.0 });
-    // // let mut colorful_cube = Mesh::from(shape::RegularPolygon::new(10., 6));
-    // if let Some(VertexAttributeValues::Float32x3(positions)) =
-    //     colorful_cube.attribute(Mesh::ATTRIBUTE_POSITION)
-    // {
-    //     // dbg!(positions);
-    //     let colors: Vec<[f32; 4]> = positions
-    //         .iter()
-    //         .map(|[r, g, b]| [(1. - *r) / 2., (1. - *g) / 2., (1. - *b) / 2., 1.])
-    //         .collect();
-    //     colorful_cube.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
-    // }
-    // commands.spawn_bundle(PbrBundle {
-    //     mesh: meshes.add(colorful_cube),
-    //     // This is the default color, but note that vertex colors are
-    //     // multiplied by the base color, so you'll likely want this to be
-    //     // white if using vertex colors.
-    //     material: materials.add(Color::rgb(1., 1., 1.).into()),
-    //     transform: Transform::from_xyz(-10.0, 10., -10.0),
-    //     ..default()
-    // });
+    if !config.nn_overlays {
+        return;
+    }
+    let steering_levels = STEERING_LEVELS.len();
+    let columns = ACTIONS + THROTTLE_LEVELS.len() - 1;
+    for (t, car, dqn_prev) in q_car.iter() {
+        let q_values = dqn_prev.q_values;
+        let min = q_values.iter().cloned().fold(f32::INFINITY, f32::min);
+        let max = q_values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let span = (max - min).max(f32::EPSILON);
+        let right = t.rotation.mul_vec3(-Vec3::X);
+        let base = t.translation + Vec3::Y * (car.size.hh + Q_BAR_LIFT);
+        let column_x = |column: usize| (column as f32 - (columns - 1) as f32 / 2.) * Q_BAR_SPACING;
+        lines.line_colored(
+            base + right * column_x(0),
+            base + right * column_x(columns - 1),
+            0.0,
+            Color::GRAY,
+        );
+        for (i, q) in q_values.iter().enumerate() {
+            // one empty column between throttle groups
+            let column = i + i / steering_levels;
+            let start = base + right * column_x(column);
+            let height = ((q - min) / span * Q_BAR_HEIGHT).max(0.02);
+            let color = match *q >= max {
+                true => Color::GREEN,
+                false => Color::WHITE,
+            };
+            lines.line_colored(start, start + Vec3::Y * height, 0.0, color);
+        }
+    }
 }
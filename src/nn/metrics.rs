@@ -0,0 +1,169 @@
+use super::params::*;
+use crate::randomization::PhysicsSample;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const RUNS_DIR: &str = "runs";
+const METRICS_FILE: &str = "metrics.jsonl";
+const PARAMS_FILE: &str = "params.json";
+const CRASH_WINDOW_SECONDS: f64 = 60.;
+// points per series kept in memory for the live plots
+const HISTORY_LEN: usize = 2000;
+
+// one line of metrics.jsonl, the same fields a tensorboard scalar has, wall_time in epoch seconds
+#[derive(Serialize)]
+struct Scalar<'a> {
+    name: &'a str,
+    step: usize,
+    wall_time: f64,
+    value: f32,
+}
+
+// scalar series of one experiment under runs/<run>, the run is named by RUN_NAME or the start time
+pub struct Metrics {
+    pub run: String,
+    writer: Option<BufWriter<File>>,
+    // set after a write failed, the live plots keep working without the file
+    disabled: bool,
+    crash_times: VecDeque<f64>,
+    history: HashMap<String, VecDeque<[f64; 2]>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let run = std::env::var("RUN_NAME").unwrap_or_else(|_| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .to_string()
+        });
+        Self {
+            run,
+            writer: None,
+            disabled: false,
+            crash_times: VecDeque::new(),
+            history: HashMap::new(),
+        }
+    }
+    pub fn dir(&self) -> String {
+        format!("{RUNS_DIR}/{}", self.run)
+    }
+    // the run directory is created with the first scalar, with the hyperparameters next to it
+    fn open(&self) -> io::Result<BufWriter<File>> {
+        let dir = self.dir();
+        fs::create_dir_all(&dir)?;
+        let params = serde_json::json!({
+            "batch_size": BATCH_SIZE,
+            "epochs": EPOCHS,
+            "decay": DECAY,
+            "learning_rate": LEARNING_RATE,
+            "sync_interval_steps": SYNC_INTERVAL_STEPS,
+            "hidden_size": HIDDEN_SIZE,
+            "step_duration": STEP_DURATION,
+            "observation_size": OBSERVATION_SIZE,
+            "sensor_rig": SENSOR_RIG,
+            "sim_seed": SIM_SEED,
+            "actions": ACTIONS,
+        });
+        fs::write(
+            format!("{dir}/{PARAMS_FILE}"),
+            serde_json::to_string_pretty(&params).unwrap(),
+        )?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("{dir}/{METRICS_FILE}"))?;
+        println!("metrics: writing {dir}");
+        Ok(BufWriter::new(file))
+    }
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.writer.is_none() {
+            self.writer = Some(self.open()?);
+        }
+        writeln!(self.writer.as_mut().unwrap(), "{line}")
+    }
+    // a full disk or a removed run directory stops the metrics file, not the training
+    fn disable(&mut self, err: io::Error) {
+        println!("metrics disabled: {err}");
+        self.disabled = true;
+        self.writer = None;
+    }
+    // recent (step, value) points of a series
+    pub fn history(&self, name: &str) -> Vec<[f64; 2]> {
+        match self.history.get(name) {
+            Some(points) => points.iter().copied().collect(),
+            None => vec![],
+        }
+    }
+    pub fn scalar(&mut self, name: &str, step: usize, value: f32) {
+        let points = self.history.entry(name.to_string()).or_default();
+        if points.len() == HISTORY_LEN {
+            points.pop_front();
+        }
+        points.push_back([step as f64, value as f64]);
+        if self.disabled {
+            return;
+        }
+        let line = serde_json::to_string(&Scalar {
+            name,
+            step,
+            wall_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64(),
+            value,
+        })
+        .unwrap();
+        if let Err(err) = self.write_line(&line) {
+            self.disable(err);
+        }
+    }
+    pub fn flush(&mut self) {
+        if let Some(Err(err)) = self.writer.as_mut().map(|writer| writer.flush()) {
+            self.disable(err);
+        }
+    }
+    // with the physics the episode ran on, so policies can be compared across car setups
+    pub fn episode(
+        &mut self,
+        step: usize,
+        episode_return: f32,
+        episode_length: usize,
+        physics: &PhysicsSample,
+    ) {
+        self.scalar("episode_return", step, episode_return);
+        self.scalar("episode_length", step, episode_length as f32);
+        for (name, value) in [
+            ("physics/mass_scale", physics.mass_scale),
+            ("physics/com_offset_y", physics.com_offset.y),
+            ("physics/com_offset_z", physics.com_offset.z),
+            ("physics/tyre_friction_scale", physics.tyre_friction_scale),
+            ("physics/torque_scale", physics.torque_scale),
+            ("physics/wheel_radius_scale", physics.wheel_radius_scale),
+            ("physics/wall_friction", physics.wall_friction),
+            ("physics/ground_friction", physics.ground_friction),
+        ] {
+            self.scalar(name, step, value);
+        }
+        self.flush();
+    }
+    pub fn crash(&mut self, step: usize, seconds: f64) {
+        self.crash_times.push_back(seconds);
+        while let Some(&at) = self.crash_times.front() {
+            if seconds - at <= CRASH_WINDOW_SECONDS {
+                break;
+            }
+            self.crash_times.pop_front();
+        }
+        let minutes = (seconds.min(CRASH_WINDOW_SECONDS) / 60.).max(1. / 60.);
+        self.scalar(
+            "crashes_per_minute",
+            step,
+            (self.crash_times.len() as f64 / minutes) as f32,
+        );
+    }
+}
@@ -1,5 +1,11 @@
+pub mod checkpoint;
 pub mod dqn;
 pub mod dqn_bevy;
+pub mod gradient;
+pub mod metrics;
+pub mod normalizer;
+pub mod observation;
 pub mod params;
 pub mod replay;
+pub mod reward;
 pub mod util;
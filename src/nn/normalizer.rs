@@ -0,0 +1,85 @@
+use super::{dqn::*, params::*};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+const VARIANCE_EPSILON: f32 = 1e-8;
+
+// running mean and variance of observation frames (Welford)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunningNormalizer {
+    pub count: f64,
+    pub mean: Vec<f32>,
+    pub m2: Vec<f32>,
+}
+
+impl RunningNormalizer {
+    pub fn new() -> Self {
+        Self {
+            count: 0.,
+            mean: vec![0.; STATE_SIZE],
+            m2: vec![0.; STATE_SIZE],
+        }
+    }
+    pub fn update(&mut self, frame: &Frame) {
+        self.count += 1.;
+        let count = self.count as f32;
+        for (i, &x) in frame.iter().enumerate() {
+            let delta = x - self.mean[i];
+            self.mean[i] += delta / count;
+            self.m2[i] += delta * (x - self.mean[i]);
+        }
+    }
+    pub fn variance(&self, i: usize) -> f32 {
+        if self.count < 2. {
+            return 1.;
+        }
+        self.m2[i] / (self.count - 1.) as f32
+    }
+    pub fn normalize(&self, frame: &Frame) -> Frame {
+        // without stats yet the raw frame is clipped to the normalized range
+        if self.count < 2. {
+            return frame.map(|x| x.clamp(-NORMALIZE_CLIP, NORMALIZE_CLIP));
+        }
+        let mut normalized = FRAME_ZERO;
+        for (i, &x) in frame.iter().enumerate() {
+            normalized[i] = ((x - self.mean[i]) / (self.variance(i) + VARIANCE_EPSILON).sqrt())
+                .clamp(-NORMALIZE_CLIP, NORMALIZE_CLIP);
+        }
+        normalized
+    }
+    // the replay buffer keeps raw frames, they are normalized with the current stats on use,
+    // the zero padding of a fresh frame stack stays zero
+    pub fn normalize_observation(&self, obs: &Observation) -> Observation {
+        if !NORMALIZE_OBSERVATION {
+            return *obs;
+        }
+        let mut normalized = OBSERVATION_ZERO;
+        for (raw, out) in obs
+            .chunks(STATE_SIZE)
+            .zip(normalized.chunks_mut(STATE_SIZE))
+        {
+            if raw.iter().all(|&x| x == 0.) {
+                continue;
+            }
+            let mut frame = FRAME_ZERO;
+            frame.copy_from_slice(raw);
+            out.copy_from_slice(&self.normalize(&frame));
+        }
+        normalized
+    }
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, json).map_err(|e| e.to_string())
+    }
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let normalizer: Self = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+        if normalizer.mean.len() != STATE_SIZE {
+            return Err(format!(
+                "normalizer has {} features, observation has {STATE_SIZE}",
+                normalizer.mean.len()
+            ));
+        }
+        Ok(normalizer)
+    }
+}
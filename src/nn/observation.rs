@@ -0,0 +1,105 @@
+use super::{dqn::*, params::*};
+use crate::{
+    car::Car,
+    config::Config,
+    sensor::{DepthCamera, Lidar},
+    suspension::Suspension,
+};
+use bevy::prelude::*;
+use std::f32::consts::PI;
+
+// size and feature flags of a frame, rows and files from another layout are rejected
+pub fn observation_layout() -> String {
+    let flags = format!(
+        "{OBS_CURVATURE} {CURVATURE_LOOKAHEAD_METERS:?} {OBS_LATERAL_OFFSET} {OBS_YAW_RATE} \
+         {OBS_WHEEL_SLIP} {OBS_SUSPENSION} {OBS_LIDAR_SECTORS} {OBS_DEPTH} \
+         {OBS_DEPTH_WIDTH}x{OBS_DEPTH_HEIGHT} {SENSOR_COUNT}"
+    );
+    // fnv-1a, stable between builds unlike the std hasher
+    let hash = flags.bytes().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{STATE_SIZE}x{FRAME_STACK}-{hash:016x}")
+}
+
+pub fn build_observation(
+    config: &Config,
+    car: &Car,
+    translation: Vec3,
+    kmh: f32,
+    vel_cos: f32,
+    pos_cos: f32,
+    yaw_rate: f32,
+    lidar: Option<&Lidar>,
+    depth_camera: Option<&DepthCamera>,
+    suspension: Option<&Suspension>,
+) -> Frame {
+    let mut features: Vec<f32> = vec![kmh / 100., vel_cos, pos_cos];
+    if OBS_CURVATURE {
+        for ahead in CURVATURE_LOOKAHEAD_METERS {
+            features.push(curvature_ahead(config, car, ahead));
+        }
+    }
+    if OBS_LATERAL_OFFSET {
+        features.push(lateral_offset(car, translation) / 5.);
+    }
+    if OBS_YAW_RATE {
+        features.push(yaw_rate);
+    }
+    if OBS_WHEEL_SLIP {
+        for slip in car.wheel_slip {
+            features.push((slip / MAX_WHEEL_SLIP).min(1.));
+        }
+    }
+    if OBS_SUSPENSION {
+        let compression = match suspension {
+            Some(suspension) => suspension.normalized(),
+            None => [0.; 4],
+        };
+        features.extend(compression);
+    }
+    if OBS_LIDAR_SECTORS > 0 {
+        let sectors = match lidar {
+            Some(lidar) => lidar.sector_ranges(OBS_LIDAR_SECTORS),
+            None => vec![0.; OBS_LIDAR_SECTORS],
+        };
+        features.extend(sectors);
+    }
+    if OBS_DEPTH {
+        let mut depth = match depth_camera {
+            Some(camera) => camera.normalized(),
+            None => vec![],
+        };
+        depth.resize(OBS_DEPTH_WIDTH * OBS_DEPTH_HEIGHT, 0.);
+        features.extend(depth);
+    }
+    features.extend(car.sensor_inputs.iter());
+
+    let mut frame: Frame = FRAME_ZERO;
+    for (o, f) in frame.iter_mut().zip(features.into_iter()) {
+        *o = f;
+    }
+    frame
+}
+
+// signed heading change of the centerline between the car and a point ahead, -1..1
+pub fn curvature_ahead(config: &Config, car: &Car, ahead: f32) -> f32 {
+    let dir_ahead = config.get_direction_by_meter(car.meters + ahead);
+    let (a, b) = (
+        Vec3::new(car.line_dir.x, 0., car.line_dir.z),
+        Vec3::new(dir_ahead.x, 0., dir_ahead.z),
+    );
+    let angle = a.cross(b).y.atan2(a.dot(b));
+    if angle.is_nan() {
+        return 0.;
+    }
+    angle / PI
+}
+
+// signed distance from the centerline, positive to the left of the line direction
+pub fn lateral_offset(car: &Car, translation: Vec3) -> f32 {
+    let mut offset = translation - car.line_pos;
+    offset.y = 0.;
+    let side = car.line_dir.cross(offset).y;
+    offset.length() * side.signum()
+}
@@ -7,13 +7,58 @@ pub const BUFFER_SIZE: usize = 10_000_000;
 pub const HIDDEN_SIZE: usize = 16;
 
 pub const STEP_DURATION: f64 = 1. / 10.;
-pub const SENSOR_COUNT: usize = 31;
+// SENSOR_RIG path and its SENSOR_COUNT rays, generated by build.rs from the SENSOR_RIG env var
+include!(concat!(env!("OUT_DIR"), "/sensor_rig.rs"));
 pub const STATE_SIZE_BASE: usize = 3;
-pub const STATE_SIZE: usize = STATE_SIZE_BASE + SENSOR_COUNT;
-pub const ACTIONS: usize = 8;
+// optional track lookahead and car dynamics features
+pub const OBS_CURVATURE: bool = true;
+pub const CURVATURE_LOOKAHEAD_METERS: [f32; 4] = [10., 25., 50., 100.];
+pub const OBS_LATERAL_OFFSET: bool = true;
+pub const OBS_YAW_RATE: bool = true;
+pub const OBS_WHEEL_SLIP: bool = true;
+pub const OBS_SUSPENSION: bool = false;
+const CURVATURE_SIZE: usize = if OBS_CURVATURE {
+    CURVATURE_LOOKAHEAD_METERS.len()
+} else {
+    0
+};
+const WHEEL_SLIP_SIZE: usize = if OBS_WHEEL_SLIP { 4 } else { 0 };
+const SUSPENSION_SIZE: usize = if OBS_SUSPENSION { 4 } else { 0 };
+pub const STATE_SIZE_TRACK: usize = CURVATURE_SIZE
+    + OBS_LATERAL_OFFSET as usize
+    + OBS_YAW_RATE as usize
+    + WHEEL_SLIP_SIZE
+    + SUSPENSION_SIZE;
+// optional lidar sector and depth camera features, 0 sectors disables lidar
+pub const OBS_LIDAR_SECTORS: usize = 0;
+pub const OBS_DEPTH: bool = false;
+pub const OBS_DEPTH_WIDTH: usize = 16;
+pub const OBS_DEPTH_HEIGHT: usize = 4;
+const DEPTH_SIZE: usize = if OBS_DEPTH {
+    OBS_DEPTH_WIDTH * OBS_DEPTH_HEIGHT
+} else {
+    0
+};
+pub const STATE_SIZE_PERCEPTION: usize = OBS_LIDAR_SECTORS + DEPTH_SIZE;
+pub const STATE_SIZE: usize =
+    STATE_SIZE_BASE + STATE_SIZE_TRACK + STATE_SIZE_PERCEPTION + SENSOR_COUNT;
+// range sensors come last in a frame
+pub const SENSOR_OFFSET: usize = STATE_SIZE_BASE + STATE_SIZE_TRACK + STATE_SIZE_PERCEPTION;
+// the network sees the last FRAME_STACK frames, newest first
+pub const FRAME_STACK: usize = 1;
+pub const OBSERVATION_SIZE: usize = STATE_SIZE * FRAME_STACK;
+pub const NORMALIZE_OBSERVATION: bool = true;
+pub const NORMALIZE_CLIP: f32 = 5.;
+// discrete action table: every throttle level is combined with every steering level,
+// positive throttle is gas, negative throttle is brake
+pub const THROTTLE_LEVELS: [f32; 3] = [1., 0., -1.];
+pub const STEERING_LEVELS: [f32; 5] = [-1., -0.5, 0., 0.5, 1.];
+pub const ACTIONS: usize = THROTTLE_LEVELS.len() * STEERING_LEVELS.len();
 pub const CARS_COUNT: usize = 1;
+pub const SIM_SEED: u64 = 0;
+// reference speed for reward scaling, top speed comes from the drivetrain and aero
 pub const SPEED_LIMIT_KMH: f32 = 100.;
 pub const SPEED_LIMIT_MPS: f32 = SPEED_LIMIT_KMH * 1000. / 3600.;
 pub const STEERING_SPEEDLIMIT_KMH: f32 = 250.;
-pub const MAX_TORQUE: f32 = 2000.;
 pub const MAX_TOI: f32 = 100.;
+pub const MAX_WHEEL_SLIP: f32 = 10.;
@@ -2,19 +2,53 @@ use std::ops::RangeFrom;
 
 use crate::db::{rb, PrismaClient};
 
-use super::{dqn::*, params::*};
+use super::{dqn::*, normalizer::RunningNormalizer, observation::observation_layout, params::*};
 use dfdx::tensor::{HasArrayData, Tensor1D, Tensor2D, TensorCreator};
 
 type StateTuple = (Observation, usize, f32, Observation, f32);
 type StateTensorsTuple = (
-    Tensor2D<BATCH_SIZE, STATE_SIZE>, // s
-    [usize; BATCH_SIZE],              // a
-    Tensor1D<BATCH_SIZE>,             // r
-    Tensor2D<BATCH_SIZE, STATE_SIZE>, // sn
-    Tensor1D<BATCH_SIZE>,             // done
+    Tensor2D<BATCH_SIZE, OBSERVATION_SIZE>, // s
+    [usize; BATCH_SIZE],                    // a
+    Tensor1D<BATCH_SIZE>,                   // r
+    Tensor2D<BATCH_SIZE, OBSERVATION_SIZE>, // sn
+    Tensor1D<BATCH_SIZE>,                   // done
 );
 
 const PERSIST_BATCH_SIZE: usize = 500;
+const LAYOUT_SEPARATOR: char = ';';
+
+// persisted rows are prefixed with the observation layout they were recorded with
+pub fn encode_observation(obs: &Observation) -> String {
+    format!(
+        "{}{LAYOUT_SEPARATOR}{}",
+        observation_layout(),
+        obs.map(|x| x.to_string()).join(",")
+    )
+}
+
+pub fn decode_observation(row: &str) -> Result<Observation, String> {
+    let (layout, values) = match row.split_once(LAYOUT_SEPARATOR) {
+        Some(split) => split,
+        None => return Err("row has no layout".to_string()),
+    };
+    let expected = observation_layout();
+    if layout != expected {
+        return Err(format!("row layout {layout}, expected {expected}"));
+    }
+    let values: Vec<f32> = values
+        .split(',')
+        .map(|x| x.parse::<f32>().map_err(|e| e.to_string()))
+        .collect::<Result<_, _>>()?;
+    if values.len() != OBSERVATION_SIZE {
+        return Err(format!(
+            "row has {} values, expected {OBSERVATION_SIZE}",
+            values.len()
+        ));
+    }
+    let mut obs = OBSERVATION_ZERO;
+    obs.copy_from_slice(&values);
+    Ok(obs)
+}
 
 pub struct ReplayBuffer {
     pub state: Vec<Observation>,
@@ -50,18 +84,22 @@ impl ReplayBuffer {
             )
         })
     }
-    pub fn get_batch_tensors(&self, sample_indexes: [usize; BATCH_SIZE]) -> StateTensorsTuple {
+    pub fn get_batch_tensors(
+        &self,
+        sample_indexes: [usize; BATCH_SIZE],
+        normalizer: &RunningNormalizer,
+    ) -> StateTensorsTuple {
         let batch: [StateTuple; BATCH_SIZE] = self.get_batch(sample_indexes);
-        let mut states: Tensor2D<BATCH_SIZE, STATE_SIZE> = Tensor2D::zeros();
+        let mut states: Tensor2D<BATCH_SIZE, OBSERVATION_SIZE> = Tensor2D::zeros();
         let mut actions: [usize; BATCH_SIZE] = [0; BATCH_SIZE];
         let mut rewards: Tensor1D<BATCH_SIZE> = Tensor1D::zeros();
-        let mut next_states: Tensor2D<BATCH_SIZE, STATE_SIZE> = Tensor2D::zeros();
+        let mut next_states: Tensor2D<BATCH_SIZE, OBSERVATION_SIZE> = Tensor2D::zeros();
         let mut done: Tensor1D<BATCH_SIZE> = Tensor1D::zeros();
         for (i, (s, a, r, s_n, d)) in batch.iter().enumerate() {
-            states.mut_data()[i] = *s;
+            states.mut_data()[i] = normalizer.normalize_observation(s);
             actions[i] = 1 * a;
             rewards.mut_data()[i] = *r;
-            next_states.mut_data()[i] = *s_n;
+            next_states.mut_data()[i] = normalizer.normalize_observation(s_n);
             done.mut_data()[i] = *d;
         }
         (states, actions, rewards, next_states, done)
@@ -102,10 +140,10 @@ impl ReplayBuffer {
                     .map(|t| {
                         let i = save_start_index + t.0;
                         return rb::create(
-                            self.state[i].map(|x| x.to_string()).join(","),
+                            encode_observation(&self.state[i]),
                             self.action[i] as i32,
                             self.reward[i] as f64,
-                            self.next_state[i].map(|x| x.to_string()).join(","),
+                            encode_observation(&self.next_state[i]),
                             self.done[i] == 1.,
                             vec![],
                         );
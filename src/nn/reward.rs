@@ -0,0 +1,148 @@
+use super::params::*;
+
+pub struct RewardInput {
+    pub crash: bool,
+    pub speed_mps: f32,
+    pub vel_cos: f32,
+    pub pos_cos: f32,
+    pub d_from_center: f32,
+    pub meters_delta: f32,
+    pub lap_time: Option<f32>,
+    pub steering_delta: f32,
+    pub wheels_off_track: usize,
+}
+
+pub trait RewardFn: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn reward(&self, input: &RewardInput) -> f32;
+}
+
+pub struct InriaReward;
+impl RewardFn for InriaReward {
+    fn name(&self) -> &'static str {
+        "inria"
+    }
+    fn reward(&self, input: &RewardInput) -> f32 {
+        if input.crash {
+            return -1.;
+        }
+        // https://team.inria.fr/rits/files/2018/02/ICRA18_EndToEndDriving_CameraReady.pdf
+        // In [13] the reward is computed as a function of the difference of angle α between the road and car’s heading and the speed v.
+        // R = v(cos α − d)
+        let mut reward =
+            input.speed_mps / SPEED_LIMIT_MPS * (input.vel_cos - input.d_from_center / 5.);
+        if input.vel_cos.is_sign_positive() && input.pos_cos.is_sign_negative() {
+            reward = -reward;
+        }
+        if reward.is_nan() {
+            return 0.;
+        }
+        reward
+    }
+}
+
+// meters driven along the polyline, 1. is a full step at speed limit
+pub struct ProgressReward;
+impl RewardFn for ProgressReward {
+    fn name(&self) -> &'static str {
+        "progress"
+    }
+    fn reward(&self, input: &RewardInput) -> f32 {
+        input.meters_delta / (SPEED_LIMIT_MPS * STEP_DURATION as f32)
+    }
+}
+
+pub struct LapTimeReward {
+    pub reference_seconds: f32,
+}
+impl RewardFn for LapTimeReward {
+    fn name(&self) -> &'static str {
+        "lap"
+    }
+    fn reward(&self, input: &RewardInput) -> f32 {
+        match input.lap_time {
+            Some(lap_time) if lap_time > 0. => self.reference_seconds / lap_time,
+            _ => 0.,
+        }
+    }
+}
+
+pub struct SmoothnessReward;
+impl RewardFn for SmoothnessReward {
+    fn name(&self) -> &'static str {
+        "smooth"
+    }
+    fn reward(&self, input: &RewardInput) -> f32 {
+        // steering is in -1..1, so a full swing is 2.
+        -input.steering_delta.abs() / 2.
+    }
+}
+
+// share of wheels on grass or gravel, catches excursions the wall crash check misses
+pub struct OffTrackReward;
+impl RewardFn for OffTrackReward {
+    fn name(&self) -> &'static str {
+        "off_track"
+    }
+    fn reward(&self, input: &RewardInput) -> f32 {
+        -(input.wheels_off_track as f32) / 4.
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RewardWeights {
+    pub inria: f32,
+    pub progress: f32,
+    pub lap_time: f32,
+    pub smoothness: f32,
+    pub off_track: f32,
+    pub lap_reference_seconds: f32,
+}
+impl Default for RewardWeights {
+    fn default() -> Self {
+        Self {
+            inria: 1.,
+            progress: 0.,
+            lap_time: 0.,
+            smoothness: 0.,
+            off_track: 0.,
+            lap_reference_seconds: 120.,
+        }
+    }
+}
+
+// built from the weights once, dqn_system rebuilds it when config.reward_weights changes
+pub struct CompositeReward {
+    pub weights: RewardWeights,
+    pub components: Vec<(f32, Box<dyn RewardFn>)>,
+}
+impl CompositeReward {
+    pub fn from_weights(weights: &RewardWeights) -> Self {
+        let all: Vec<(f32, Box<dyn RewardFn>)> = vec![
+            (weights.inria, Box::new(InriaReward) as Box<dyn RewardFn>),
+            (weights.progress, Box::new(ProgressReward)),
+            (
+                weights.lap_time,
+                Box::new(LapTimeReward {
+                    reference_seconds: weights.lap_reference_seconds,
+                }),
+            ),
+            (weights.smoothness, Box::new(SmoothnessReward)),
+            (weights.off_track, Box::new(OffTrackReward)),
+        ];
+        Self {
+            weights: weights.clone(),
+            components: all.into_iter().filter(|(w, _)| *w != 0.).collect(),
+        }
+    }
+    // returns the weighted sum and every weighted component by name
+    pub fn evaluate(&self, input: &RewardInput) -> (f32, Vec<(&'static str, f32)>) {
+        let components: Vec<(&'static str, f32)> = self
+            .components
+            .iter()
+            .map(|(weight, f)| (f.name(), weight * f.reward(input)))
+            .collect();
+        let total: f32 = components.iter().map(|(_, r)| r).sum();
+        (total, components)
+    }
+}
@@ -1,3 +1,4 @@
+use super::params::*;
 use std::time::Instant;
 
 pub fn log_training(
@@ -9,7 +10,7 @@ pub fn log_training(
 ) {
     let log = [
         String::from(if use_random { "?" } else { " " }),
-        action.to_string(),
+        action_label(action),
         " ".to_string(),
         String::from(if reward > 0. { "+" } else { "-" }),
         format!("{:.2}", reward.abs()),
@@ -23,7 +24,7 @@ pub fn log_training(
 }
 pub fn log_action_reward(action: usize, reward: f32) {
     let log = [
-        action.to_string(),
+        action_label(action),
         " ".to_string(),
         String::from(if reward > 0. { "+" } else { "-" }),
         format!("{:.2}", reward.abs()),
@@ -31,29 +32,23 @@ pub fn log_action_reward(action: usize, reward: f32) {
     .join("");
     println!("{log:?}");
 }
+pub fn log_reward_components(components: &[(&'static str, f32)]) {
+    let log = components
+        .iter()
+        .map(|(name, r)| format!("{name} {:+.2}", r))
+        .collect::<Vec<String>>()
+        .join(" ");
+    println!("{log:?}");
+}
 
-const ONE: f32 = 1.;
-const ZERO: f32 = 0.;
-pub fn map_action_to_car(a: usize) -> (f32, f32, f32, f32) {
-    let gas = if a == 0 || a == 4 || a == 5 {
-        ONE
-    } else {
-        ZERO
-    };
-    let brake = if a == 1 || a == 6 || a == 7 {
-        ONE
-    } else {
-        ZERO
-    };
-    let left = if a == 2 || a == 4 || a == 6 {
-        ONE
-    } else {
-        ZERO
-    };
-    let right = if a == 3 || a == 5 || a == 7 {
-        ONE
-    } else {
-        ZERO
-    };
-    (gas, brake, left, right)
+pub fn map_action_to_car(a: usize) -> (f32, f32, f32) {
+    let throttle = THROTTLE_LEVELS[a / STEERING_LEVELS.len()];
+    let steering = STEERING_LEVELS[a % STEERING_LEVELS.len()];
+    let gas = throttle.max(0.);
+    let brake = (-throttle).max(0.);
+    (gas, brake, steering)
+}
+pub fn action_label(a: usize) -> String {
+    let (gas, brake, steering) = map_action_to_car(a);
+    format!("{a}[{:+.1} {:+.1}]", gas - brake, steering)
 }
@@ -0,0 +1,109 @@
+use crate::nn::params::SIM_SEED;
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+// every random draw of sensors, randomization, spawn poses and exploration goes through here,
+// the systems drawing from it are chained in main so the draw order is the same every run
+pub struct SimRng {
+    pub rng: StdRng,
+}
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+    // standard normal sample, Box-Muller
+    pub fn gaussian(&mut self) -> f32 {
+        let u1: f32 = self.rng.gen_range(f32::EPSILON..1.);
+        let u2: f32 = self.rng.gen_range(0.0..1.);
+        (-2. * u1.ln()).sqrt() * (2. * PI * u2).cos()
+    }
+}
+impl Default for SimRng {
+    fn default() -> Self {
+        Self::new(SIM_SEED)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NoiseModel {
+    // gaussian range noise in meters
+    #[serde(default)]
+    pub range_std: f32,
+    // probability that a reading is lost
+    #[serde(default)]
+    pub dropout: f32,
+    // range resolution in meters, 0 disables
+    #[serde(default)]
+    pub quantization: f32,
+    // readings are delayed by this many frames, the fixed timestep makes one physics step per frame
+    #[serde(default)]
+    pub latency_steps: usize,
+}
+
+impl NoiseModel {
+    pub fn is_perfect(&self) -> bool {
+        self.range_std == 0. && self.dropout == 0. && self.quantization == 0.
+    }
+    // a dropped reading looks like nothing was hit
+    pub fn apply(&self, toi: Option<f32>, max_range: f32, rng: &mut SimRng) -> Option<f32> {
+        if self.is_perfect() {
+            return toi;
+        }
+        if self.dropout > 0. && rng.rng.gen_range(0.0..1.) < self.dropout {
+            return None;
+        }
+        let mut toi = toi?;
+        if self.range_std > 0. {
+            toi += self.range_std * rng.gaussian();
+        }
+        if self.quantization > 0. {
+            toi = (toi / self.quantization).round() * self.quantization;
+        }
+        match toi {
+            x if x >= max_range => None,
+            x => Some(x.max(0.)),
+        }
+    }
+}
+
+// counts frames, one reading is pushed per frame
+#[derive(Component, Debug, Default)]
+pub struct SensorLatency {
+    pub buffer: VecDeque<Vec<f32>>,
+}
+impl SensorLatency {
+    pub fn push(&mut self, inputs: Vec<f32>, steps: usize) -> Vec<f32> {
+        self.buffer.push_back(inputs);
+        while self.buffer.len() > steps + 1 {
+            self.buffer.pop_front();
+        }
+        self.buffer.front().unwrap().clone()
+    }
+}
+
+// (gas, brake, steering) commands reach the car this many frames late, one physics step each
+#[derive(Component, Debug)]
+pub struct ActuatorDelay {
+    pub steps: usize,
+    pub buffer: VecDeque<(f32, f32, f32)>,
+}
+impl ActuatorDelay {
+    pub fn new(steps: usize) -> Self {
+        Self {
+            steps,
+            buffer: VecDeque::new(),
+        }
+    }
+    pub fn push(&mut self, controls: (f32, f32, f32)) -> (f32, f32, f32) {
+        self.buffer.push_back(controls);
+        while self.buffer.len() > self.steps + 1 {
+            self.buffer.pop_front();
+        }
+        *self.buffer.front().unwrap()
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{car::*, config::*, track::*};
+use crate::{car::*, config::*, surface::SurfaceMap, terrain::Terrain, track::*};
 use bevy::prelude::*;
 use bevy_rapier3d::{na::Point3, prelude::*, rapier::prelude::ColliderShape};
 use obj::*;
@@ -8,14 +8,18 @@ use std::cmp::Ordering;
 use std::fs::File;
 use std::io::BufReader;
 
-pub fn track_polyline_start_system(mut commands: Commands, mut config: ResMut<Config>) {
+pub fn track_polyline_start_system(
+    mut commands: Commands,
+    mut config: ResMut<Config>,
+    terrain: Res<Terrain>,
+) {
     let obj_path = "assets/track-polyline.obj";
     let polyline_buf = BufReader::new(File::open(obj_path).unwrap());
     let model = raw::parse_obj(polyline_buf).unwrap();
     let vertices: Vec<Point3<Real>> = model
         .positions
         .iter()
-        .map(|pos| Point3::new(pos.0, pos.1, pos.2))
+        .map(|pos| Point3::new(pos.0, pos.1 + terrain.height_at(pos.0, pos.2), pos.2))
         .collect();
 
     let polyline = Polyline::new(vertices.clone(), None);
@@ -42,6 +46,7 @@ pub fn track_polyline_start_system(mut commands: Commands, mut config: ResMut<Co
     }
     config.meters_shift = config.meters[config.segment_i as usize];
     config.track_length = meters;
+    config.surfaces = SurfaceMap::load(&config.surfaces_path);
 
     println!(
         "track length: {meters:.1} polyline shift: {:.1}",
@@ -61,7 +66,12 @@ pub fn track_polyline_start_system(mut commands: Commands, mut config: ResMut<Co
         )));
 }
 
-pub fn progress_system(config: Res<Config>, mut cars: Query<(&Transform, &mut Car, Entity)>) {
+pub fn progress_system(
+    time: Res<Time>,
+    config: Res<Config>,
+    mut cars: Query<(&Transform, &mut Car, Entity)>,
+) {
+    let seconds = time.seconds_since_startup();
     let polyline = config.polyline.as_ref().unwrap();
     let mut board: Vec<(Entity, f32)> = Vec::new();
     for (tr, mut car, e) in cars.iter_mut() {
@@ -87,6 +97,13 @@ pub fn progress_system(config: Res<Config>, mut cars: Query<(&Transform, &mut Ca
                 if meters - car.meters > config.track_length - 10. {
                     meters = -(config.track_length - meters);
                 }
+                if car.meters - meters > config.track_length - 10. {
+                    car.lap += 1;
+                    if let Some(started_at) = car.lap_started_at {
+                        car.lap_time = Some((seconds - started_at) as f32);
+                    }
+                    car.lap_started_at = Some(seconds);
+                }
                 let dir = Vec3::from(segment.direction().unwrap());
                 car.line_dir = dir;
                 car.line_pos = Vec3::from(segment.a) + dir * m;
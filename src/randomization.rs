@@ -0,0 +1,124 @@
+use crate::{car::Car, noise::SimRng};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+
+pub const RANDOMIZATION_DEFAULT: &str = "assets/randomization/default.json";
+
+pub const WALL_FRICTION: f32 = 0.1;
+pub const GROUND_FRICTION: f32 = 1.;
+
+#[derive(Component)]
+pub struct TrackWall;
+#[derive(Component)]
+pub struct TrackGround;
+
+// [min, max] ranges, scales multiply the vehicle's nominal values
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RandomizationProfile {
+    pub enabled: bool,
+    pub mass_scale: [f32; 2],
+    pub com_offset_y: [f32; 2],
+    pub com_offset_z: [f32; 2],
+    pub tyre_friction_scale: [f32; 2],
+    pub torque_scale: [f32; 2],
+    pub wheel_radius_scale: [f32; 2],
+    pub wall_friction: [f32; 2],
+    pub ground_friction: [f32; 2],
+}
+
+impl Default for RandomizationProfile {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mass_scale: [1., 1.],
+            com_offset_y: [0., 0.],
+            com_offset_z: [0., 0.],
+            tyre_friction_scale: [1., 1.],
+            torque_scale: [1., 1.],
+            wheel_radius_scale: [1., 1.],
+            wall_friction: [WALL_FRICTION, WALL_FRICTION],
+            ground_friction: [GROUND_FRICTION, GROUND_FRICTION],
+        }
+    }
+}
+
+fn sample_range(range: [f32; 2], rng: &mut SimRng) -> f32 {
+    if range[1] <= range[0] {
+        return range[0];
+    }
+    rng.rng.gen_range(range[0]..range[1])
+}
+
+impl RandomizationProfile {
+    pub fn load(path: &str) -> Self {
+        let reader = BufReader::new(File::open(path).unwrap());
+        serde_json::from_reader(reader).unwrap()
+    }
+    pub fn sample(&self, rng: &mut SimRng) -> PhysicsSample {
+        if !self.enabled {
+            return PhysicsSample::default();
+        }
+        PhysicsSample {
+            mass_scale: sample_range(self.mass_scale, rng),
+            com_offset: Vec3::new(
+                0.,
+                sample_range(self.com_offset_y, rng),
+                sample_range(self.com_offset_z, rng),
+            ),
+            tyre_friction_scale: sample_range(self.tyre_friction_scale, rng),
+            torque_scale: sample_range(self.torque_scale, rng),
+            wheel_radius_scale: sample_range(self.wheel_radius_scale, rng),
+            wall_friction: sample_range(self.wall_friction, rng),
+            ground_friction: sample_range(self.ground_friction, rng),
+        }
+    }
+}
+
+// physics of one episode, kept on the car so it can be logged with the episode
+#[derive(Component, Debug, Clone)]
+pub struct PhysicsSample {
+    pub mass_scale: f32,
+    pub com_offset: Vec3,
+    pub tyre_friction_scale: f32,
+    pub torque_scale: f32,
+    pub wheel_radius_scale: f32,
+    pub wall_friction: f32,
+    pub ground_friction: f32,
+}
+
+impl Default for PhysicsSample {
+    fn default() -> Self {
+        Self {
+            mass_scale: 1.,
+            com_offset: Vec3::ZERO,
+            tyre_friction_scale: 1.,
+            torque_scale: 1.,
+            wheel_radius_scale: 1.,
+            wall_friction: WALL_FRICTION,
+            ground_friction: GROUND_FRICTION,
+        }
+    }
+}
+
+// walls and ground are shared, they follow the newest episode
+pub fn randomization_track_system(
+    q_sample: Query<(&Car, &PhysicsSample), Added<PhysicsSample>>,
+    mut track_set: ParamSet<(
+        Query<&mut Friction, With<TrackWall>>,
+        Query<&mut Friction, With<TrackGround>>,
+    )>,
+) {
+    for (car, sample) in q_sample.iter() {
+        for mut friction in track_set.p0().iter_mut() {
+            friction.coefficient = sample.wall_friction;
+        }
+        for mut friction in track_set.p1().iter_mut() {
+            friction.coefficient = sample.ground_friction;
+        }
+        println!("episode physics car {}: {:?}", car.index, sample);
+    }
+}
@@ -0,0 +1,559 @@
+use crate::{car::*, config::Config, mesh::bevy_mesh, weather::Weather};
+use bevy::{app::AppExit, prelude::*};
+use bevy_rapier3d::{parry::shape::Cylinder, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const REPLAY_DIR: &str = "replays";
+const REPLAY_MAGIC: &[u8; 4] = b"CRPL";
+const REPLAY_VERSION: u32 = 1;
+const SCRUB_SECONDS: f32 = 5.;
+const SPEED_MIN: f32 = 0.125;
+const SPEED_MAX: f32 = 8.;
+// recorded frames are written out this often, a crash or quit loses at most this much
+const FLUSH_SECONDS: f64 = 5.;
+
+// assets the session ran with, playback loads the same ones
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub vehicle_paths: Vec<String>,
+    pub terrain_path: String,
+    pub weather_path: String,
+    // session seconds when the recording started, the weather clock at frame 0
+    #[serde(default)]
+    pub started_at: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayEvent {
+    Crash,
+    // lap time in seconds, 0 for the first crossing
+    Lap(f32),
+    // the car came back after a despawn
+    Reset,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayCarFrame {
+    pub index: usize,
+    pub vehicle: usize,
+    pub hid: bool,
+    pub pose: Transform,
+    pub wheels: [Transform; 4],
+    // gas, brake, handbrake, steering
+    pub inputs: [f32; 4],
+    pub events: Vec<ReplayEvent>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayFrame {
+    // since the recording started
+    pub seconds: f32,
+    pub cars: Vec<ReplayCarFrame>,
+}
+
+// file layout: magic, version, json header length and header, then frames until the end
+// frame: seconds, car count, per car index, vehicle, hid, 5 poses, 4 inputs, events
+fn write_pose(buf: &mut Vec<u8>, t: &Transform) {
+    for v in t
+        .translation
+        .to_array()
+        .iter()
+        .chain(t.rotation.to_array().iter())
+    {
+        buf.extend(v.to_le_bytes());
+    }
+}
+
+fn write_frame(buf: &mut Vec<u8>, frame: &ReplayFrame) {
+    buf.extend(frame.seconds.to_le_bytes());
+    buf.push(frame.cars.len() as u8);
+    for car in frame.cars.iter() {
+        buf.push(car.index as u8);
+        buf.push(car.vehicle as u8);
+        buf.push(car.hid as u8);
+        write_pose(buf, &car.pose);
+        for wheel in car.wheels.iter() {
+            write_pose(buf, wheel);
+        }
+        for v in car.inputs.iter() {
+            buf.extend(v.to_le_bytes());
+        }
+        buf.push(car.events.len() as u8);
+        for event in car.events.iter() {
+            let (kind, value): (u8, f32) = match event {
+                ReplayEvent::Crash => (0, 0.),
+                ReplayEvent::Lap(lap_time) => (1, *lap_time),
+                ReplayEvent::Reset => (2, 0.),
+            };
+            buf.push(kind);
+            buf.extend(value.to_le_bytes());
+        }
+    }
+}
+
+fn read_u8(r: &mut impl Read) -> Option<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b).ok()?;
+    Some(b[0])
+}
+
+fn read_f32(r: &mut impl Read) -> Option<f32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b).ok()?;
+    Some(f32::from_le_bytes(b))
+}
+
+fn read_pose(r: &mut impl Read) -> Option<Transform> {
+    let mut v = [0.; 7];
+    for x in v.iter_mut() {
+        *x = read_f32(r)?;
+    }
+    Some(
+        Transform::from_xyz(v[0], v[1], v[2])
+            .with_rotation(Quat::from_xyzw(v[3], v[4], v[5], v[6])),
+    )
+}
+
+fn read_frame(r: &mut impl Read) -> Option<ReplayFrame> {
+    let seconds = read_f32(r)?;
+    let car_count = read_u8(r)?;
+    let mut cars = Vec::with_capacity(car_count as usize);
+    for _ in 0..car_count {
+        let index = read_u8(r)? as usize;
+        let vehicle = read_u8(r)? as usize;
+        let hid = read_u8(r)? != 0;
+        let pose = read_pose(r)?;
+        let mut wheels = [Transform::default(); 4];
+        for wheel in wheels.iter_mut() {
+            *wheel = read_pose(r)?;
+        }
+        let mut inputs = [0.; 4];
+        for input in inputs.iter_mut() {
+            *input = read_f32(r)?;
+        }
+        let mut events = vec![];
+        for _ in 0..read_u8(r)? {
+            let kind = read_u8(r)?;
+            let value = read_f32(r)?;
+            events.push(match kind {
+                0 => ReplayEvent::Crash,
+                1 => ReplayEvent::Lap(value),
+                _ => ReplayEvent::Reset,
+            });
+        }
+        cars.push(ReplayCarFrame {
+            index,
+            vehicle,
+            hid,
+            pose,
+            wheels,
+            inputs,
+            events,
+        });
+    }
+    Some(ReplayFrame { seconds, cars })
+}
+
+#[derive(Default)]
+pub struct Recorder {
+    pub recording: bool,
+    pub started_at: f64,
+    pub frame_count: usize,
+    pub path: String,
+    // the file is written from the start, frames not flushed yet wait in frames
+    writer: Option<BufWriter<File>>,
+    pub frames: Vec<u8>,
+    pub bytes: usize,
+    flushed_at: f64,
+    // per car index, to turn state changes into events
+    laps: HashMap<usize, usize>,
+    crashed: HashSet<usize>,
+    present: HashSet<usize>,
+}
+
+impl Recorder {
+    pub fn start(&mut self, seconds: f64, config: &Config) {
+        let header = ReplayHeader {
+            version: REPLAY_VERSION,
+            vehicle_paths: config.vehicle_paths.clone(),
+            terrain_path: config.terrain_path.clone(),
+            weather_path: config.weather_path.clone(),
+            started_at: seconds,
+        };
+        let header = serde_json::to_vec(&header).unwrap();
+        std::fs::create_dir_all(REPLAY_DIR).unwrap();
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = format!("{REPLAY_DIR}/{stamp}.replay");
+        let mut writer = BufWriter::new(File::create(&path).unwrap());
+        writer.write_all(REPLAY_MAGIC).unwrap();
+        writer.write_all(&REPLAY_VERSION.to_le_bytes()).unwrap();
+        writer
+            .write_all(&(header.len() as u32).to_le_bytes())
+            .unwrap();
+        writer.write_all(&header).unwrap();
+        writer.flush().unwrap();
+        println!("replay recording to {path}");
+        *self = Recorder {
+            recording: true,
+            started_at: seconds,
+            path,
+            writer: Some(writer),
+            flushed_at: seconds,
+            ..default()
+        };
+    }
+    // complete frames only, playback stops at a cut off last frame
+    pub fn flush(&mut self, seconds: f64) {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write_all(&self.frames).unwrap();
+            writer.flush().unwrap();
+        }
+        self.bytes += self.frames.len();
+        self.frames.clear();
+        self.flushed_at = seconds;
+    }
+    pub fn save(&mut self, seconds: f64) {
+        self.flush(seconds);
+        self.recording = false;
+        self.writer = None;
+        println!(
+            "replay saved to {}: {} frames {} bytes",
+            self.path, self.frame_count, self.bytes
+        );
+    }
+}
+
+pub fn replay_record_system(
+    input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    config: Res<Config>,
+    mut recorder: ResMut<Recorder>,
+    q_car: Query<(&Car, &Transform, &Children, Option<&HID>)>,
+    q_wheel: Query<&Transform, With<Wheel>>,
+    q_colliding_entities: Query<&CollidingEntities>,
+    q_name: Query<&Name>,
+    mut exit: EventReader<AppExit>,
+) {
+    let seconds = time.seconds_since_startup();
+    if input.just_pressed(KeyCode::F6) {
+        match recorder.recording {
+            true => recorder.save(seconds),
+            false => recorder.start(seconds, &config),
+        }
+    }
+    if !recorder.recording {
+        return;
+    }
+    if exit.iter().next().is_some() {
+        recorder.save(seconds);
+        return;
+    }
+    let mut frame = ReplayFrame {
+        seconds: (time.seconds_since_startup() - recorder.started_at) as f32,
+        cars: vec![],
+    };
+    let mut present: HashSet<usize> = HashSet::new();
+    for (car, t, children, hid) in q_car.iter() {
+        let mut wheels = [Transform::default(); 4];
+        for (i, wheel_entity) in car.wheels.iter().take(4).enumerate() {
+            if let Ok(wt) = q_wheel.get(*wheel_entity) {
+                wheels[i] = *wt;
+            }
+        }
+        let crash = is_crashing(children, &q_colliding_entities, &q_name);
+        let mut events = vec![];
+        if crash && !recorder.crashed.contains(&car.index) {
+            events.push(ReplayEvent::Crash);
+        }
+        match crash {
+            true => recorder.crashed.insert(car.index),
+            false => recorder.crashed.remove(&car.index),
+        };
+        if let Some(lap) = recorder.laps.insert(car.index, car.lap) {
+            if lap != car.lap {
+                events.push(ReplayEvent::Lap(car.lap_time.unwrap_or(0.)));
+            }
+        }
+        if recorder.frame_count > 0 && !recorder.present.contains(&car.index) {
+            events.push(ReplayEvent::Reset);
+        }
+        present.insert(car.index);
+        frame.cars.push(ReplayCarFrame {
+            index: car.index,
+            vehicle: car.vehicle,
+            hid: hid.is_some(),
+            pose: *t,
+            wheels,
+            inputs: [car.gas, car.brake, car.handbrake, car.steering],
+            events,
+        });
+    }
+    recorder.present = present;
+    write_frame(&mut recorder.frames, &frame);
+    recorder.frame_count += 1;
+    if seconds - recorder.flushed_at > FLUSH_SECONDS {
+        recorder.flush(seconds);
+    }
+}
+
+#[derive(Component)]
+pub struct ReplayText;
+
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub frames: Vec<ReplayFrame>,
+    pub seconds: f32,
+    pub speed: f32,
+    pub paused: bool,
+    // render entities per recorded car index, chassis and wheels
+    pub cars: HashMap<usize, (Entity, [Entity; 4])>,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Self {
+        let bytes = std::fs::read(path).unwrap();
+        let mut r: &[u8] = &bytes;
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic).unwrap();
+        assert!(&magic == REPLAY_MAGIC, "{path} is not a replay");
+        let mut word = [0u8; 4];
+        r.read_exact(&mut word).unwrap();
+        let version = u32::from_le_bytes(word);
+        assert!(
+            version == REPLAY_VERSION,
+            "replay version {version} unsupported"
+        );
+        r.read_exact(&mut word).unwrap();
+        let (header, mut r) = r.split_at(u32::from_le_bytes(word) as usize);
+        let header: ReplayHeader = serde_json::from_slice(header).unwrap();
+        let mut frames = vec![];
+        while let Some(frame) = read_frame(&mut r) {
+            frames.push(frame);
+        }
+        println!(
+            "replay: {path} frames: {} seconds: {:.1}",
+            frames.len(),
+            frames.last().map_or(0., |f| f.seconds)
+        );
+        Self {
+            header,
+            frames,
+            seconds: 0.,
+            speed: 1.,
+            paused: false,
+            cars: HashMap::new(),
+        }
+    }
+    pub fn duration(&self) -> f32 {
+        self.frames.last().map_or(0., |f| f.seconds)
+    }
+    // frames around the playback time and the blend between them
+    pub fn frames_at(&self, seconds: f32) -> Option<(&ReplayFrame, &ReplayFrame, f32)> {
+        let i = self.frames.partition_point(|f| f.seconds <= seconds);
+        let a = self.frames.get(i.saturating_sub(1))?;
+        let b = self.frames.get(i).unwrap_or(a);
+        let span = b.seconds - a.seconds;
+        let x = match span > 0. {
+            true => ((seconds - a.seconds) / span).clamp(0., 1.),
+            false => 0.,
+        };
+        Some((a, b, x))
+    }
+}
+
+fn blend(a: &Transform, b: &Transform, x: f32) -> Transform {
+    Transform::from_translation(a.translation.lerp(b.translation, x))
+        .with_rotation(a.rotation.slerp(b.rotation, x))
+}
+
+pub fn replay_start_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut config: ResMut<Config>,
+    asset_server: Res<AssetServer>,
+    mut replay: ResMut<Replay>,
+) {
+    config.vehicle_paths = replay.header.vehicle_paths.clone();
+    load_vehicles(&mut config, &asset_server, &mut meshes, &mut materials);
+
+    // one render car per recorded index, the first human driven one gets the cameras
+    let mut cars: Vec<(usize, usize, bool)> = vec![];
+    for car in replay.frames.iter().flat_map(|f| f.cars.iter()) {
+        if !cars.iter().any(|(index, _, _)| *index == car.index) {
+            cars.push((car.index, car.vehicle, car.hid));
+        }
+    }
+    let hid_index = cars.iter().find(|c| c.2).or(cars.first()).map(|c| c.0);
+    let wheel_material = materials.add(Color::rgba(0.1, 0.1, 0.1, 0.7).into());
+    for (index, vehicle_i, _hid) in cars {
+        let vehicle = &config.vehicles[vehicle_i % config.vehicles.len()];
+        let model = config.vehicle_models[vehicle_i % config.vehicle_models.len()].clone();
+        let model_transform = vehicle.model.transform();
+        let car_id = commands
+            .spawn()
+            .insert(Name::new("replay-car"))
+            .insert_bundle(SpatialBundle::default())
+            .with_children(|children| {
+                model.spawn(children, model_transform);
+            })
+            .id();
+        if Some(index) == hid_index {
+            commands.entity(car_id).insert(HID);
+        }
+        let mut wheels = [car_id; 4];
+        for (i, wheel) in wheels.iter_mut().enumerate() {
+            let def = match i < 2 {
+                true => &vehicle.wheels.front,
+                false => &vehicle.wheels.back,
+            };
+            *wheel = commands
+                .spawn()
+                .insert(Name::new("replay-wheel"))
+                .insert_bundle(PbrBundle {
+                    mesh: meshes.add(bevy_mesh(
+                        Cylinder::new(def.half_width, def.radius).to_trimesh(50),
+                    )),
+                    material: wheel_material.clone(),
+                    ..default()
+                })
+                .id();
+        }
+        replay.cars.insert(index, (car_id, wheels));
+    }
+
+    let medium: Handle<Font> = asset_server.load("fonts/FiraMono-Medium.ttf");
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(80.),
+                    left: Val::Px(2.0),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: medium,
+                    font_size: 16.0,
+                    color: Color::BLACK,
+                },
+            ),
+            ..default()
+        })
+        .insert(ReplayText);
+    println!(
+        "Replay controls:
+        Space       - pause
+        Left/Right  - scrub {SCRUB_SECONDS}s
+        Up/Down     - faster/slower
+        Home        - restart"
+    );
+}
+
+pub fn replay_playback_system(
+    time: Res<Time>,
+    input: Res<Input<KeyCode>>,
+    mut replay: ResMut<Replay>,
+    mut weather: ResMut<Weather>,
+    mut q_render: Query<(&mut Transform, &mut Visibility)>,
+    mut q_text: Query<&mut Text, With<ReplayText>>,
+) {
+    if input.just_pressed(KeyCode::Space) {
+        replay.paused = !replay.paused;
+    }
+    if input.just_pressed(KeyCode::Up) {
+        replay.speed = (replay.speed * 2.).min(SPEED_MAX);
+    }
+    if input.just_pressed(KeyCode::Down) {
+        replay.speed = (replay.speed / 2.).max(SPEED_MIN);
+    }
+    let mut seconds = replay.seconds;
+    if input.just_pressed(KeyCode::Left) {
+        seconds -= SCRUB_SECONDS;
+    }
+    if input.just_pressed(KeyCode::Right) {
+        seconds += SCRUB_SECONDS;
+    }
+    if input.just_pressed(KeyCode::Home) {
+        seconds = 0.;
+    }
+    let from = seconds;
+    if !replay.paused {
+        seconds += time.delta_seconds() * replay.speed;
+    }
+    replay.seconds = seconds.clamp(0., replay.duration());
+    // the weather follows the cursor as it was in the recorded session
+    weather.clock = Some((replay.header.started_at + replay.seconds as f64) as f32);
+
+    let (a, b, x) = match replay.frames_at(replay.seconds) {
+        Some(frames) => frames,
+        None => return,
+    };
+    // events of the frames passed since the last update, none when scrubbing back
+    let first = replay.frames.partition_point(|f| f.seconds <= from);
+    let last = replay
+        .frames
+        .partition_point(|f| f.seconds <= replay.seconds);
+    for frame in replay.frames.get(first..last).unwrap_or_default() {
+        for car in frame.cars.iter().filter(|c| !c.events.is_empty()) {
+            println!(
+                "replay {:.1}s car {}: {:?}",
+                frame.seconds, car.index, car.events
+            );
+        }
+    }
+    let mut hid_inputs: Option<[f32; 4]> = None;
+    for (index, (car_id, wheels)) in replay.cars.iter() {
+        let car_a = a.cars.iter().find(|c| c.index == *index);
+        let car_b = b.cars.iter().find(|c| c.index == *index).or(car_a);
+        let is_visible = car_a.is_some();
+        if let (Some(car_a), Some(car_b)) = (car_a, car_b) {
+            if let Ok((mut t, _)) = q_render.get_mut(*car_id) {
+                *t = blend(&car_a.pose, &car_b.pose, x);
+            }
+            for i in 0..4 {
+                if let Ok((mut t, _)) = q_render.get_mut(wheels[i]) {
+                    *t = blend(&car_a.wheels[i], &car_b.wheels[i], x);
+                }
+            }
+            if car_a.hid {
+                hid_inputs = Some(car_a.inputs);
+            }
+        }
+        for e in wheels.iter().chain(std::iter::once(car_id)) {
+            if let Ok((_, mut visibility)) = q_render.get_mut(*e) {
+                if visibility.is_visible != is_visible {
+                    visibility.is_visible = is_visible;
+                }
+            }
+        }
+    }
+
+    let mut text = q_text.single_mut();
+    let mut status = format!(
+        "replay {:.1}/{:.1}s x{:.2}{}",
+        replay.seconds,
+        replay.duration(),
+        replay.speed,
+        if replay.paused { " paused" } else { "" }
+    );
+    if let Some([gas, brake, handbrake, steering]) = hid_inputs {
+        status += &format!(
+            " gas {gas:.1} brake {brake:.1} handbrake {handbrake:.1} steering {steering:.1}"
+        );
+    }
+    text.sections[0].value = status;
+}
@@ -0,0 +1,336 @@
+use crate::{
+    car::{Car, CarSize},
+    config::Config,
+    nn::params::*,
+    noise::*,
+    track::STATIC_GROUP,
+    weather::Weather,
+};
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorFan {
+    pub count: usize,
+    pub from_deg: f32,
+    pub to_deg: f32,
+}
+
+// x and z are in car half width / half length, y is the mounting height in meters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorMount {
+    pub x: f32,
+    #[serde(default)]
+    pub y: Option<f32>,
+    pub z: f32,
+    #[serde(default)]
+    pub angles_deg: Vec<f32>,
+    #[serde(default)]
+    pub fan: Option<SensorFan>,
+}
+
+impl SensorMount {
+    pub fn angles(&self) -> Vec<f32> {
+        let mut angles = self.angles_deg.clone();
+        if let Some(fan) = &self.fan {
+            for i in 0..fan.count {
+                let x = match fan.count {
+                    1 => 0.5,
+                    n => i as f32 / (n - 1) as f32,
+                };
+                angles.push(fan.from_deg + (fan.to_deg - fan.from_deg) * x);
+            }
+        }
+        angles
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorRig {
+    pub name: String,
+    pub max_range: f32,
+    pub height: f32,
+    pub collision_groups: u32,
+    pub mounts: Vec<SensorMount>,
+    #[serde(default)]
+    pub noise: NoiseModel,
+}
+
+impl Default for SensorRig {
+    fn default() -> Self {
+        Self {
+            name: "none".to_string(),
+            max_range: MAX_TOI,
+            height: 0.1,
+            collision_groups: STATIC_GROUP,
+            mounts: vec![],
+            noise: NoiseModel::default(),
+        }
+    }
+}
+
+impl SensorRig {
+    pub fn load(path: &str) -> Self {
+        let reader = BufReader::new(File::open(path).unwrap());
+        let rig: SensorRig = serde_json::from_reader(reader).unwrap();
+        assert!(
+            rig.ray_count() == SENSOR_COUNT,
+            "sensor rig {path} has {} rays, the build has {SENSOR_COUNT} from {SENSOR_RIG}, \
+             rebuild with SENSOR_RIG={path}",
+            rig.ray_count()
+        );
+        println!("sensor rig: {} rays: {}", rig.name, rig.ray_count());
+        rig
+    }
+    pub fn ray_count(&self) -> usize {
+        self.mounts.iter().map(|m| m.angles().len()).sum()
+    }
+    // ray origins and directions in car space
+    pub fn rays(&self, size: &CarSize) -> Vec<(Vec3, Quat)> {
+        let mut rays: Vec<(Vec3, Quat)> = vec![];
+        for mount in self.mounts.iter() {
+            let pos = Vec3::new(
+                mount.x * size.hw,
+                mount.y.unwrap_or(self.height),
+                mount.z * size.hl,
+            );
+            for angle in mount.angles() {
+                rays.push((pos, Quat::from_rotation_y(angle.to_radians())));
+            }
+        }
+        rays
+    }
+}
+
+pub const PERCEPTION_EXPORT_DIR: &str = "exports";
+
+// multi-layer scanner, rays follow the car body so slopes and kerbs are visible
+#[derive(Component, Debug)]
+pub struct Lidar {
+    pub layers_deg: Vec<f32>,
+    pub horizontal_rays: usize,
+    pub max_range: f32,
+    pub mount: Vec3,
+    pub rate_hz: f32,
+    pub noise: NoiseModel,
+    pub next_scan_at: f64,
+    pub scans: usize,
+    // hit points of the last scan in car space
+    pub points: Vec<Vec3>,
+    // 1 - toi / max_range for every ray, layer by layer, 0 when nothing is hit
+    pub ranges: Vec<f32>,
+}
+
+impl Default for Lidar {
+    fn default() -> Self {
+        Self {
+            layers_deg: vec![-15., -10., -5., -2., 0., 2.],
+            horizontal_rays: 90,
+            max_range: 100.,
+            mount: Vec3::new(0., 1., 0.),
+            rate_hz: 10.,
+            noise: NoiseModel::default(),
+            next_scan_at: 0.,
+            scans: 0,
+            points: vec![],
+            ranges: vec![],
+        }
+    }
+}
+
+impl Lidar {
+    // closest hit of every horizontal sector over all layers, sector 0 starts straight ahead
+    pub fn sector_ranges(&self, sectors: usize) -> Vec<f32> {
+        let mut out = vec![0.; sectors];
+        if self.horizontal_rays == 0 {
+            return out;
+        }
+        for (i, &r) in self.ranges.iter().enumerate() {
+            let ray = i % self.horizontal_rays;
+            let sector = ray * sectors / self.horizontal_rays;
+            out[sector] = out[sector].max(r);
+        }
+        out
+    }
+    pub fn export(&self, car_index: usize) -> std::io::Result<()> {
+        let dir = format!("{PERCEPTION_EXPORT_DIR}/lidar");
+        std::fs::create_dir_all(&dir)?;
+        let mut xyz = String::new();
+        for p in self.points.iter() {
+            xyz.push_str(&format!("{} {} {}\n", p.x, p.y, p.z));
+        }
+        std::fs::write(format!("{dir}/car{car_index}-{:06}.xyz", self.scans), xyz)
+    }
+}
+
+// low resolution depth image from a grid of rays, no gpu rendering involved
+#[derive(Component, Debug)]
+pub struct DepthCamera {
+    pub width: usize,
+    pub height: usize,
+    pub fov_h_deg: f32,
+    pub fov_v_deg: f32,
+    pub pitch_deg: f32,
+    pub max_range: f32,
+    pub mount: Vec3,
+    pub rate_hz: f32,
+    pub noise: NoiseModel,
+    pub next_capture_at: f64,
+    pub captures: usize,
+    // meters, row by row from the top left, max_range when nothing is hit
+    pub depth: Vec<f32>,
+}
+
+impl Default for DepthCamera {
+    fn default() -> Self {
+        Self {
+            width: OBS_DEPTH_WIDTH,
+            height: OBS_DEPTH_HEIGHT,
+            fov_h_deg: 90.,
+            fov_v_deg: 30.,
+            pitch_deg: -5.,
+            max_range: 100.,
+            mount: Vec3::new(0., 0.8, 1.),
+            rate_hz: 10.,
+            noise: NoiseModel::default(),
+            next_capture_at: 0.,
+            captures: 0,
+            depth: vec![],
+        }
+    }
+}
+
+impl DepthCamera {
+    pub fn normalized(&self) -> Vec<f32> {
+        self.depth.iter().map(|d| 1. - d / self.max_range).collect()
+    }
+    pub fn export(&self, car_index: usize) -> std::io::Result<()> {
+        let dir = format!("{PERCEPTION_EXPORT_DIR}/depth");
+        std::fs::create_dir_all(&dir)?;
+        let csv = self
+            .depth
+            .chunks(self.width.max(1))
+            .map(|row| {
+                row.iter()
+                    .map(|d| format!("{d:.3}"))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        std::fs::write(
+            format!("{dir}/car{car_index}-{:06}.csv", self.captures),
+            csv,
+        )
+    }
+}
+
+fn perception_filter() -> QueryFilter<'static> {
+    QueryFilter::new().exclude_dynamic().exclude_sensors()
+}
+
+pub fn lidar_system(
+    time: Res<Time>,
+    rapier_context: Res<RapierContext>,
+    config: Res<Config>,
+    weather: Res<Weather>,
+    mut q_car: Query<(&Car, &Transform, &mut Lidar)>,
+    mut lines: ResMut<DebugLines>,
+    mut rng: ResMut<SimRng>,
+) {
+    let seconds = time.seconds_since_startup();
+    let filter = perception_filter();
+    for (car, t, mut lidar) in q_car.iter_mut() {
+        if seconds < lidar.next_scan_at {
+            continue;
+        }
+        lidar.next_scan_at = seconds + 1. / lidar.rate_hz as f64;
+        lidar.scans += 1;
+
+        let origin = t.translation + t.rotation.mul_vec3(lidar.mount);
+        let visible_range = weather.visibility(lidar.max_range);
+        let mut points: Vec<Vec3> = vec![];
+        let mut ranges: Vec<f32> = vec![];
+        for layer in lidar.layers_deg.iter() {
+            let pitch = Quat::from_rotation_x(-layer.to_radians());
+            for i in 0..lidar.horizontal_rays {
+                let yaw = Quat::from_rotation_y(2. * PI * i as f32 / lidar.horizontal_rays as f32);
+                let local_dir = yaw.mul_quat(pitch).mul_vec3(Vec3::Z);
+                let dir = t.rotation.mul_vec3(local_dir);
+                let hit = rapier_context
+                    .cast_ray(origin, dir, visible_range, false, filter)
+                    .map(|(_e, toi)| toi);
+                match lidar.noise.apply(hit, visible_range, &mut rng) {
+                    Some(toi) if toi > 0. => {
+                        points.push(lidar.mount + local_dir * toi);
+                        ranges.push(1. - toi / lidar.max_range);
+                        if config.show_rays {
+                            let p = origin + dir * toi;
+                            lines.line_colored(p, p + Vec3::Y * 0.1, 0.0, Color::CYAN);
+                        }
+                    }
+                    _ => ranges.push(0.),
+                }
+            }
+        }
+        lidar.points = points;
+        lidar.ranges = ranges;
+        if config.export_perception {
+            if let Err(err) = lidar.export(car.index) {
+                println!("lidar export failed: {err}");
+            }
+        }
+    }
+}
+
+pub fn depth_camera_system(
+    time: Res<Time>,
+    rapier_context: Res<RapierContext>,
+    config: Res<Config>,
+    weather: Res<Weather>,
+    mut q_car: Query<(&Car, &Transform, &mut DepthCamera)>,
+    mut rng: ResMut<SimRng>,
+) {
+    let seconds = time.seconds_since_startup();
+    let filter = perception_filter();
+    for (car, t, mut camera) in q_car.iter_mut() {
+        if seconds < camera.next_capture_at {
+            continue;
+        }
+        camera.next_capture_at = seconds + 1. / camera.rate_hz as f64;
+        camera.captures += 1;
+
+        let origin = t.translation + t.rotation.mul_vec3(camera.mount);
+        let visible_range = weather.visibility(camera.max_range);
+        let (fov_h, fov_v) = (camera.fov_h_deg.to_radians(), camera.fov_v_deg.to_radians());
+        let mut depth: Vec<f32> = Vec::with_capacity(camera.width * camera.height);
+        for row in 0..camera.height {
+            let v = (row as f32 + 0.5) / camera.height as f32 - 0.5;
+            let pitch = Quat::from_rotation_x(-camera.pitch_deg.to_radians() + v * fov_v);
+            for col in 0..camera.width {
+                let u = (col as f32 + 0.5) / camera.width as f32 - 0.5;
+                let yaw = Quat::from_rotation_y(-u * fov_h);
+                let dir = t.rotation.mul_vec3(yaw.mul_quat(pitch).mul_vec3(Vec3::Z));
+                let hit = rapier_context
+                    .cast_ray(origin, dir, visible_range, false, filter)
+                    .map(|(_e, toi)| toi);
+                let toi = match camera.noise.apply(hit, visible_range, &mut rng) {
+                    Some(toi) => toi,
+                    None => camera.max_range,
+                };
+                depth.push(toi);
+            }
+        }
+        camera.depth = depth;
+        if config.export_perception {
+            if let Err(err) = camera.export(car.index) {
+                println!("depth export failed: {err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{car::Car, config::Config};
+use bevy::prelude::*;
+use bevy_rapier3d::{na::Point3, prelude::*};
+use parry3d::query::PointQueryWithLocation;
+use parry3d::shape::SegmentPointLocation;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+
+pub const SURFACES_DEFAULT: &str = "assets/surfaces/default.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SurfaceKind {
+    Asphalt,
+    Kerb,
+    Grass,
+    Gravel,
+}
+impl Default for SurfaceKind {
+    fn default() -> Self {
+        SurfaceKind::Asphalt
+    }
+}
+impl SurfaceKind {
+    pub fn is_off_track(&self) -> bool {
+        matches!(self, SurfaceKind::Grass | SurfaceKind::Gravel)
+    }
+}
+
+// friction scales the tyre peak grip, wet_friction replaces it on a fully wet track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurfaceDef {
+    pub kind: SurfaceKind,
+    pub friction: f32,
+    pub wet_friction: f32,
+    pub rolling_resistance: f32,
+}
+
+// a band of lateral offsets from the centerline, positive to the left,
+// optionally limited to a stretch of meters from the start line, the same frame as Car.meters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurfaceZone {
+    pub kind: SurfaceKind,
+    pub offset: [f32; 2],
+    #[serde(default)]
+    pub meters: Option<[f32; 2]>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurfaceMap {
+    pub name: String,
+    // 0 dry .. 1 fully wet before any rain
+    #[serde(default)]
+    pub wetness: f32,
+    pub surfaces: Vec<SurfaceDef>,
+    // the first matching zone wins, points outside all zones are grass
+    pub zones: Vec<SurfaceZone>,
+}
+
+impl Default for SurfaceMap {
+    fn default() -> Self {
+        Self {
+            name: "none".to_string(),
+            wetness: 0.,
+            surfaces: vec![],
+            zones: vec![],
+        }
+    }
+}
+
+impl SurfaceMap {
+    pub fn load(path: &str) -> Self {
+        let reader = BufReader::new(File::open(path).unwrap());
+        let map: SurfaceMap = serde_json::from_reader(reader).unwrap();
+        println!("surfaces: {} zones: {}", map.name, map.zones.len());
+        map
+    }
+    pub fn kind_at(&self, meters: f32, offset: f32) -> SurfaceKind {
+        for zone in self.zones.iter() {
+            let in_offset = offset >= zone.offset[0] && offset <= zone.offset[1];
+            let in_meters = match zone.meters {
+                Some([from, to]) => meters >= from && meters <= to,
+                None => true,
+            };
+            if in_offset && in_meters {
+                return zone.kind;
+            }
+        }
+        SurfaceKind::Grass
+    }
+    // (grip scale, rolling resistance) of a surface, rain only makes the track wetter
+    pub fn grip(&self, kind: SurfaceKind, rain_wetness: f32) -> (f32, f32) {
+        let wetness = self.wetness.max(rain_wetness);
+        match self.surfaces.iter().find(|s| s.kind == kind) {
+            Some(s) => (
+                s.friction + (s.wet_friction - s.friction) * wetness,
+                s.rolling_resistance,
+            ),
+            None => (1., 0.),
+        }
+    }
+}
+
+// meters from the start line like Car.meters and signed lateral offset of a point
+pub fn track_position(config: &Config, point: Vec3) -> Option<(f32, f32)> {
+    let polyline = config.polyline.as_ref()?;
+    let location = polyline.project_local_point_and_get_location(&Point3::from(point), true);
+    let (segment_i, segment_location) = location.1;
+    let segment = polyline.segment(segment_i);
+    let along = match segment_location {
+        SegmentPointLocation::OnVertex(0) => 0.,
+        SegmentPointLocation::OnVertex(_) => segment.length(),
+        SegmentPointLocation::OnEdge(uv) => uv[1] * segment.length(),
+    };
+    let dir = Vec3::from(segment.direction()?);
+    let on_line = Vec3::from(segment.a) + dir * along;
+    let mut offset = point - on_line;
+    offset.y = 0.;
+    let side = dir.cross(offset).y;
+    let meters = (config.meters[segment_i as usize] + along - config.meters_shift)
+        .rem_euclid(config.track_length);
+    Some((meters, offset.length() * side.signum()))
+}
+
+pub fn surface_system(
+    config: Res<Config>,
+    mut q_car: Query<&mut Car>,
+    q_wheel: Query<&Transform, Without<Car>>,
+) {
+    for mut car in q_car.iter_mut() {
+        let mut wheel_surface = [SurfaceKind::Asphalt; 4];
+        for (i, wheel_entity) in car.wheels.iter().enumerate() {
+            if let Ok(t) = q_wheel.get(*wheel_entity) {
+                if let Some((meters, offset)) = track_position(&config, t.translation) {
+                    wheel_surface[i] = config.surfaces.kind_at(meters, offset);
+                }
+            }
+        }
+        if car.wheel_surface != wheel_surface {
+            car.wheel_surface = wheel_surface;
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{
+    car::{Car, Wheel},
+    vehicle::SuspensionDef,
+};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+// wheel order follows car.wheels: front left, front right, back left, back right,
+// +X is the car's left with +Z forward
+#[derive(Component, Debug)]
+pub struct Suspension {
+    // wheel rest positions in car space
+    pub anchors: [Vec3; 4],
+    pub travel: [f32; 4],
+    pub stiffness: [f32; 4],
+    // front and back anti-roll bar rates
+    pub anti_roll: [f32; 2],
+    // meters, positive when the wheel is pushed up into the body
+    pub compression: [f32; 4],
+}
+
+impl Suspension {
+    pub fn new(def: &SuspensionDef, anchors: [Vec3; 4]) -> Self {
+        Self {
+            anchors,
+            travel: [
+                def.front.travel,
+                def.front.travel,
+                def.back.travel,
+                def.back.travel,
+            ],
+            stiffness: [
+                def.front.stiffness,
+                def.front.stiffness,
+                def.back.stiffness,
+                def.back.stiffness,
+            ],
+            anti_roll: [def.anti_roll_front, def.anti_roll_back],
+            compression: [0.; 4],
+        }
+    }
+    // compression as a fraction of the travel, -1..1
+    pub fn normalized(&self) -> [f32; 4] {
+        let mut out = [0.; 4];
+        for i in 0..4 {
+            if self.travel[i] > 0. {
+                out[i] = (self.compression[i] / self.travel[i]).clamp(-1., 1.);
+            }
+        }
+        out
+    }
+}
+
+// seconds the physics advances per frame, a force times this is its impulse for one step;
+// the frame time would spike the impulses on slow frames while rapier still steps a fixed dt
+pub fn physics_dt(rapier_config: &RapierConfiguration, time: &Time) -> f32 {
+    match rapier_config.timestep_mode {
+        TimestepMode::Fixed { dt, .. } => dt,
+        _ => time.delta_seconds(),
+    }
+}
+
+// suspension and tyres add their impulses on top, each frame starts from zero
+pub fn impulse_reset_system(mut q_impulse: Query<&mut ExternalImpulse>) {
+    for mut impulse in q_impulse.iter_mut() {
+        impulse.impulse = Vec3::ZERO;
+        impulse.torque_impulse = Vec3::ZERO;
+    }
+}
+
+pub fn suspension_system(
+    time: Res<Time>,
+    rapier_config: Res<RapierConfiguration>,
+    mut q_car: Query<(
+        &Car,
+        &Transform,
+        &ReadMassProperties,
+        &mut Suspension,
+        &mut ExternalImpulse,
+    )>,
+    mut q_wheel: Query<(&Transform, &mut ExternalImpulse), (With<Wheel>, Without<Car>)>,
+) {
+    let d_seconds = physics_dt(&rapier_config, &time);
+    for (car, t, mass, mut suspension, mut car_impulse) in q_car.iter_mut() {
+        if car.wheels.len() != 4 {
+            continue;
+        }
+        let inverse = t.rotation.inverse();
+        for (i, wheel_entity) in car.wheels.iter().enumerate() {
+            if let Ok((wt, _)) = q_wheel.get(*wheel_entity) {
+                let local = inverse.mul_vec3(wt.translation - t.translation);
+                suspension.compression[i] = local.y - suspension.anchors[i].y;
+            }
+        }
+
+        // the bar pushes the more compressed wheel down and lifts the body on that side
+        let up = t.rotation.mul_vec3(Vec3::Y);
+        let com = t.translation + t.rotation.mul_vec3(mass.0.local_center_of_mass);
+        for (axle, (left, right)) in [(0, 1), (2, 3)].into_iter().enumerate() {
+            let rate = suspension.anti_roll[axle];
+            if rate == 0. {
+                continue;
+            }
+            let force = rate * (suspension.compression[left] - suspension.compression[right]);
+            let impulse = up * force * d_seconds;
+            for (wheel_i, sign) in [(left, 1.), (right, -1.)] {
+                if let Ok((_, mut wheel_impulse)) = q_wheel.get_mut(car.wheels[wheel_i]) {
+                    wheel_impulse.impulse -= impulse * sign;
+                }
+                let anchor = t.translation + t.rotation.mul_vec3(suspension.anchors[wheel_i]);
+                car_impulse.impulse += impulse * sign;
+                car_impulse.torque_impulse += (anchor - com).cross(impulse * sign);
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{
+    brakes::Brakes,
+    car::*,
+    config::Config,
+    drivetrain::Drivetrain,
+    nn::{dqn_bevy::CarDqnPrev, params::ACTIONS},
+    surface::track_position,
+    suspension::Suspension,
+    tyre::Tyre,
+};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const TELEMETRY_DIR: &str = "telemetry";
+
+// one csv per session, opened on the first sample
+#[derive(Default)]
+pub struct Telemetry {
+    pub path: Option<String>,
+    pub rows: usize,
+    writer: Option<BufWriter<File>>,
+    next_at: f64,
+    // set after a write failed, like the metrics sink
+    disabled: bool,
+}
+
+fn header() -> String {
+    let per_wheel = |name: &str| (0..4).map(|i| format!("{name}_{i}")).collect::<Vec<_>>();
+    let mut columns: Vec<String> = [
+        "seconds",
+        "car",
+        "vehicle",
+        "hid",
+        "speed_kmh",
+        "gas",
+        "brake",
+        "handbrake",
+        "steering",
+        "gear",
+        "rpm",
+    ]
+    .iter()
+    .map(|c| c.to_string())
+    .collect();
+    for name in [
+        "angvel",
+        "torque",
+        "brake_pressure",
+        "slip_ratio",
+        "slip_angle",
+        "compression",
+        "surface",
+    ] {
+        columns.extend(per_wheel(name));
+    }
+    for name in ["meters", "lap", "offset", "reward", "action"] {
+        columns.push(name.to_string());
+    }
+    columns.extend((0..ACTIONS).map(|i| format!("q_{i}")));
+    columns.join(",")
+}
+
+impl Telemetry {
+    fn open(&mut self) -> io::Result<BufWriter<File>> {
+        std::fs::create_dir_all(TELEMETRY_DIR)?;
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let path = format!("{TELEMETRY_DIR}/{stamp}.csv");
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writeln!(writer, "{}", header())?;
+        println!("telemetry: writing {path}");
+        self.path = Some(path);
+        Ok(writer)
+    }
+    fn write_rows(&mut self, rows: &[String]) -> io::Result<()> {
+        if self.writer.is_none() {
+            self.writer = Some(self.open()?);
+        }
+        let writer = self.writer.as_mut().unwrap();
+        for row in rows.iter() {
+            writeln!(writer, "{row}")?;
+        }
+        writer.flush()?;
+        self.rows += rows.len();
+        Ok(())
+    }
+}
+
+pub fn telemetry_system(
+    time: Res<Time>,
+    config: Res<Config>,
+    mut telemetry: ResMut<Telemetry>,
+    q_car: Query<(
+        &Car,
+        &Velocity,
+        &Transform,
+        Option<&Drivetrain>,
+        &Brakes,
+        &Suspension,
+        &CarDqnPrev,
+        Option<&HID>,
+    )>,
+    q_wheel: Query<(&Transform, &Velocity, &ExternalForce, Option<&Tyre>), With<Wheel>>,
+) {
+    let seconds = time.seconds_since_startup();
+    if !config.telemetry
+        || config.telemetry_hz <= 0.
+        || telemetry.disabled
+        || seconds < telemetry.next_at
+    {
+        return;
+    }
+    telemetry.next_at = seconds + 1. / config.telemetry_hz as f64;
+    let mut rows: Vec<String> = vec![];
+    for (car, v, t, drivetrain, brakes, suspension, dqn_prev, hid) in q_car.iter() {
+        let (gear, rpm) = drivetrain.map_or((0, 0.), |d| (d.gear, d.rpm));
+        let mut row: Vec<String> = vec![
+            format!("{seconds:.3}"),
+            car.index.to_string(),
+            car.vehicle.to_string(),
+            (hid.is_some() as u8).to_string(),
+            format!("{:.2}", v.linvel.length() * 3.6),
+            format!("{:.2}", car.gas),
+            format!("{:.2}", car.brake),
+            format!("{:.2}", car.handbrake),
+            format!("{:.2}", car.steering),
+            gear.to_string(),
+            format!("{rpm:.0}"),
+        ];
+        let mut angvel = [0.; 4];
+        let mut torque = [0.; 4];
+        let mut slip_ratio = car.wheel_slip;
+        let mut slip_angle = [0.; 4];
+        for (i, wheel_entity) in car.wheels.iter().take(4).enumerate() {
+            if let Ok((wt, wv, f, tyre)) = q_wheel.get(*wheel_entity) {
+                let axle = wt.rotation.mul_vec3(Vec3::Y);
+                angvel[i] = wv.angvel.dot(axle);
+                torque[i] = f.torque.dot(axle);
+                if let Some(tyre) = tyre {
+                    slip_ratio[i] = tyre.slip_ratio;
+                    slip_angle[i] = tyre.slip_angle;
+                }
+            }
+        }
+        for values in [
+            angvel,
+            torque,
+            brakes.pressure,
+            slip_ratio,
+            slip_angle,
+            suspension.compression,
+        ] {
+            row.extend(values.iter().map(|x| format!("{x:.3}")));
+        }
+        row.extend(
+            car.wheel_surface
+                .iter()
+                .map(|s| format!("{s:?}").to_lowercase()),
+        );
+        let offset = track_position(&config, t.translation).map_or(0., |(_, offset)| offset);
+        row.push(format!("{:.2}", car.meters));
+        row.push(car.lap.to_string());
+        row.push(format!("{offset:.2}"));
+        row.push(format!("{:.3}", dqn_prev.prev_reward));
+        row.push(dqn_prev.prev_action.to_string());
+        row.extend(dqn_prev.q_values.iter().map(|q| format!("{q:.3}")));
+        rows.push(row.join(","));
+    }
+    if let Err(err) = telemetry.write_rows(&rows) {
+        println!("telemetry disabled: {err}");
+        telemetry.disabled = true;
+        telemetry.writer = None;
+    }
+}
@@ -0,0 +1,150 @@
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::texture::{CompressedImageFormats, ImageType};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+
+pub const TERRAIN_DEFAULT: &str = "assets/terrain/flat.json";
+
+// the heightmap is stretched over the ground rectangle, black is 0 and white max_height,
+// without a heightmap the ground is flat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerrainDef {
+    pub name: String,
+    #[serde(default)]
+    pub heightmap: Option<String>,
+    #[serde(default)]
+    pub max_height: f32,
+    pub center: [f32; 2],
+    pub size: [f32; 2],
+    // meters between ground grid points
+    pub resolution: f32,
+}
+
+pub struct Terrain {
+    pub def: TerrainDef,
+    pub width: usize,
+    pub height: usize,
+    // 0..1 samples, row by row from the -z edge, columns from the -x edge
+    pub samples: Vec<f32>,
+}
+
+impl Terrain {
+    pub fn load(path: &str) -> Self {
+        let reader = BufReader::new(File::open(path).unwrap());
+        let def: TerrainDef = serde_json::from_reader(reader).unwrap();
+        let (width, height, samples) = match &def.heightmap {
+            Some(heightmap) => read_heightmap(&format!("assets/{heightmap}")),
+            None => (1, 1, vec![0.]),
+        };
+        println!("terrain: {} heightmap {width}x{height}", def.name);
+        Self {
+            def,
+            width,
+            height,
+            samples,
+        }
+    }
+    pub fn min(&self) -> Vec2 {
+        Vec2::from(self.def.center) - Vec2::from(self.def.size) / 2.
+    }
+    // bilinear height in meters, edges are clamped
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        if self.def.heightmap.is_none() {
+            return 0.;
+        }
+        let uv = (Vec2::new(x, z) - self.min()) / Vec2::from(self.def.size);
+        let px = uv.x.clamp(0., 1.) * (self.width - 1) as f32;
+        let pz = uv.y.clamp(0., 1.) * (self.height - 1) as f32;
+        let (x0, z0) = (px.floor() as usize, pz.floor() as usize);
+        let (x1, z1) = ((x0 + 1).min(self.width - 1), (z0 + 1).min(self.height - 1));
+        let (fx, fz) = (px - x0 as f32, pz - z0 as f32);
+        let s = |x: usize, z: usize| self.samples[z * self.width + x];
+        let top = s(x0, z0) + (s(x1, z0) - s(x0, z0)) * fx;
+        let bottom = s(x0, z1) + (s(x1, z1) - s(x0, z1)) * fx;
+        (top + (bottom - top) * fz) * self.def.max_height
+    }
+    pub fn grid_size(&self) -> (usize, usize) {
+        let cells = |size: f32| ((size / self.def.resolution).ceil() as usize).max(1) + 1;
+        (cells(self.def.size[1]), cells(self.def.size[0]))
+    }
+    // heights for a rapier heightfield centered on the terrain, rows along z, column major
+    pub fn heightfield(&self) -> (Vec<f32>, usize, usize) {
+        let (rows, cols) = self.grid_size();
+        let (min, size) = (self.min(), Vec2::from(self.def.size));
+        let mut heights = vec![0.; rows * cols];
+        for col in 0..cols {
+            for row in 0..rows {
+                let x = min.x + size.x * col as f32 / (cols - 1) as f32;
+                let z = min.y + size.y * row as f32 / (rows - 1) as f32;
+                heights[col * rows + row] = self.height_at(x, z);
+            }
+        }
+        (heights, rows, cols)
+    }
+    // vertices relative to the terrain center, like the heightfield
+    pub fn mesh(&self) -> Mesh {
+        let (rows, cols) = self.grid_size();
+        let (min, size) = (self.min(), Vec2::from(self.def.size));
+        let step = size / Vec2::new((cols - 1) as f32, (rows - 1) as f32);
+        let mut positions: Vec<[f32; 3]> = Vec::with_capacity(rows * cols);
+        let mut normals: Vec<[f32; 3]> = Vec::with_capacity(rows * cols);
+        let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(rows * cols);
+        for row in 0..rows {
+            for col in 0..cols {
+                let x = min.x + step.x * col as f32;
+                let z = min.y + step.y * row as f32;
+                let [center_x, center_z] = self.def.center;
+                positions.push([x - center_x, self.height_at(x, z), z - center_z]);
+                let dx = self.height_at(x + step.x, z) - self.height_at(x - step.x, z);
+                let dz = self.height_at(x, z + step.y) - self.height_at(x, z - step.y);
+                let normal = Vec3::new(-dx / (2. * step.x), 1., -dz / (2. * step.y)).normalize();
+                normals.push(normal.into());
+                uvs.push([
+                    col as f32 / (cols - 1) as f32,
+                    row as f32 / (rows - 1) as f32,
+                ]);
+            }
+        }
+        let mut indices: Vec<u32> = Vec::with_capacity((rows - 1) * (cols - 1) * 6);
+        for row in 0..rows - 1 {
+            for col in 0..cols - 1 {
+                let i = (row * cols + col) as u32;
+                let (right, down) = (i + 1, i + cols as u32);
+                indices.extend([i, down, right, right, down, down + 1]);
+            }
+        }
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+}
+
+// 8 bit images use the first channel, 16 bit grayscale the full range
+fn read_heightmap(path: &str) -> (usize, usize, Vec<f32>) {
+    let bytes = std::fs::read(path).unwrap();
+    let extension = path.rsplit('.').next().unwrap_or("png");
+    let image = Image::from_buffer(
+        &bytes,
+        ImageType::Extension(extension),
+        CompressedImageFormats::NONE,
+        false,
+    )
+    .unwrap();
+    let size = image.texture_descriptor.size;
+    let (width, height) = (size.width as usize, size.height as usize);
+    let bytes_per_pixel = image.data.len() / (width * height);
+    let samples = image
+        .data
+        .chunks(bytes_per_pixel)
+        .map(|p| match bytes_per_pixel {
+            2 => u16::from_le_bytes([p[0], p[1]]) as f32 / u16::MAX as f32,
+            _ => p[0] as f32 / u8::MAX as f32,
+        })
+        .collect();
+    (width, height, samples)
+}
@@ -1,4 +1,4 @@
-use crate::config::Config;
+use crate::{config::Config, randomization::*, terrain::Terrain};
 use bevy::prelude::*;
 use bevy::render::mesh::{Indices, PrimitiveTopology};
 use bevy_rapier3d::na::Point3;
@@ -10,11 +10,14 @@ use std::fs::File;
 use std::io::BufReader;
 
 pub const STATIC_GROUP: u32 = 0b010;
+// road and terrain, kept apart from STATIC_GROUP so range sensors see walls only
+pub const GROUND_GROUP: u32 = 0b100;
 
 pub fn track_start_system(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    terrain: Res<Terrain>,
 ) {
     let geoms = models();
     for obj_path in geoms.into_iter() {
@@ -30,8 +33,9 @@ pub fn track_start_system(
                 [
                     v.position[0],
                     match is_road {
-                        true => 0., // fix small deviations from 0. after blender obj triangulation export
-                        false => v.position[1],
+                        // flat on the terrain, drops the small deviations of the blender obj export
+                        true => terrain.height_at(v.position[0], v.position[2]),
+                        false => v.position[1] + terrain.height_at(v.position[0], v.position[2]),
                     },
                     v.position[2],
                 ]
@@ -81,59 +85,56 @@ pub fn track_start_system(
                 ..default()
             })
             .id();
-        if !is_road {
+        commands
+            .entity(id)
+            .insert(Collider::from(ColliderShape::trimesh(vertices, indices)))
+            .insert(ColliderScale::Absolute(Vec3::ONE))
+            .insert(Restitution::coefficient(0.));
+        if is_road {
+            commands
+                .entity(id)
+                .insert(CollisionGroups::new(GROUND_GROUP, u32::MAX))
+                .insert(Friction::coefficient(GROUND_FRICTION))
+                .insert(TrackGround);
+        } else {
             commands
                 .entity(id)
-                .insert(Collider::from(ColliderShape::trimesh(vertices, indices)))
-                .insert(ColliderScale::Absolute(Vec3::ONE))
                 .insert(CollisionGroups::new(STATIC_GROUP, u32::MAX))
                 .insert(Friction {
                     combine_rule: CoefficientCombineRule::Average,
-                    coefficient: 0.1,
+                    coefficient: WALL_FRICTION,
                     ..default()
                 })
-                .insert(Restitution::coefficient(0.));
+                .insert(TrackWall);
         }
     }
-    let multiplier: usize = 10;
-    let scale = 280. / multiplier as f32;
-    let num_cols: usize = 2 * multiplier;
-    let num_rows: usize = 3 * multiplier;
-    let hx = num_cols as f32 * scale;
-    let hy = 0.5;
-    let hz = num_rows as f32 * scale;
-    let ground_size: Vec3 = 2. * Vec3::new(hx, hy, hz);
-    let heights: Vec<Real> = vec![hy; num_rows * num_cols];
+    let (heights, num_rows, num_cols) = terrain.heightfield();
+    let [size_x, size_z] = terrain.def.size;
+    let [center_x, center_z] = terrain.def.center;
     commands
         .spawn()
         .insert(Name::new("road-heightfield"))
         .insert_bundle(PbrBundle {
-            mesh: meshes.add(Mesh::from(shape::Box {
-                max_x: hx,
-                min_x: -hx,
-                max_y: hy,
-                min_y: -hy,
-                max_z: hz,
-                min_z: -hz,
-            })),
+            mesh: meshes.add(terrain.mesh()),
             material: materials.add(Color::rgba(0.2, 0.35, 0.2, 0.5).into()),
             // material: materials.add(Color::rgb(0.1, 0.1, 0.15).into()),
             ..default()
         })
         .insert(RigidBody::Fixed)
-        .insert_bundle(TransformBundle::from_transform(Transform::from_xyz(
-            -350., -hy, 570.,
-        )))
         .insert(Collider::heightfield(
             heights,
             num_rows,
             num_cols,
-            ground_size.into(),
+            Vec3::new(size_x, 1., size_z),
         ))
+        .insert_bundle(TransformBundle::from_transform(Transform::from_xyz(
+            center_x, 0., center_z,
+        )))
         .insert(ColliderScale::Absolute(Vec3::ONE))
-        .insert(CollisionGroups::new(STATIC_GROUP, u32::MAX))
-        .insert(Friction::coefficient(1.))
-        .insert(Restitution::coefficient(0.));
+        .insert(CollisionGroups::new(GROUND_GROUP, u32::MAX))
+        .insert(Friction::coefficient(GROUND_FRICTION))
+        .insert(Restitution::coefficient(0.))
+        .insert(TrackGround);
 }
 
 pub const ASSET_ROAD: &str = "assets/road.obj";
@@ -150,6 +151,7 @@ pub fn track_decorations_start_system(
     asset_server: Res<AssetServer>,
     mut commands: Commands,
     config: Res<Config>,
+    terrain: Res<Terrain>,
 ) {
     let gl_object = asset_server.load("overheadLights.glb#Scene0");
     commands.spawn_bundle(SceneBundle {
@@ -157,7 +159,7 @@ pub fn track_decorations_start_system(
         transform: Transform::from_scale(Vec3::ONE * 15.)
             .with_translation(Vec3::new(
                 config.translation.x + 1.65,
-                0.,
+                terrain.height_at(config.translation.x, config.translation.z),
                 config.translation.z + 1.65,
             ))
             .with_rotation(config.quat.mul_quat(Quat::from_rotation_y(PI))),
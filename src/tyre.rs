@@ -0,0 +1,161 @@
+use crate::{
+    car::{Car, Wheel},
+    config::Config,
+    suspension::{physics_dt, Suspension},
+    weather::Weather,
+};
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// magic formula coefficients, d is the peak friction coefficient
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PacejkaDef {
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub e: f32,
+}
+
+impl PacejkaDef {
+    // force per newton of load for a slip ratio or a slip angle in radians
+    pub fn eval(&self, slip: f32) -> f32 {
+        let bx = self.b * slip;
+        self.d * (self.c * (bx - self.e * (bx - bx.atan())).atan()).sin()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TyreDef {
+    pub longitudinal: PacejkaDef,
+    pub lateral: PacejkaDef,
+    #[serde(default)]
+    pub rolling_resistance: f32,
+}
+
+#[derive(Component, Debug)]
+pub struct Tyre {
+    pub def: TyreDef,
+    // multiplies both peak coefficients, the randomized tyre and ground friction
+    pub grip: f32,
+    pub grounded: bool,
+    pub load: f32,
+    pub slip_ratio: f32,
+    pub slip_angle: f32,
+    pub force_longitudinal: f32,
+    pub force_lateral: f32,
+}
+
+impl Tyre {
+    pub fn new(def: &TyreDef, grip: f32) -> Self {
+        Self {
+            def: def.clone(),
+            grip,
+            grounded: false,
+            load: 0.,
+            slip_ratio: 0.,
+            slip_angle: 0.,
+            force_longitudinal: 0.,
+            force_lateral: 0.,
+        }
+    }
+}
+
+// below this speed the slip ratio uses a fixed denominator, otherwise it explodes at standstill
+const SLIP_MIN_SPEED: f32 = 1.;
+const CONTACT_MARGIN: f32 = 0.05;
+
+pub fn tyre_system(
+    time: Res<Time>,
+    rapier_config: Res<RapierConfiguration>,
+    rapier_context: Res<RapierContext>,
+    config: Res<Config>,
+    weather: Res<Weather>,
+    q_car: Query<(&Car, &Transform, &Suspension)>,
+    mut q_wheel: Query<
+        (
+            &Wheel,
+            &Transform,
+            &Velocity,
+            &mut Tyre,
+            &mut ExternalImpulse,
+        ),
+        Without<Car>,
+    >,
+    mut lines: ResMut<DebugLines>,
+) {
+    let d_seconds = physics_dt(&rapier_config, &time);
+    let filter = QueryFilter::new().exclude_dynamic().exclude_sensors();
+    for (car, t, suspension) in q_car.iter() {
+        let up = t.rotation.mul_vec3(Vec3::Y);
+        let car_forward = t.rotation.mul_vec3(Vec3::Z);
+        for (i, wheel_entity) in car.wheels.iter().enumerate() {
+            let (wheel, wt, v, mut tyre, mut impulse) = match q_wheel.get_mut(*wheel_entity) {
+                Ok(w) => w,
+                Err(_) => continue,
+            };
+            let hit = rapier_context.cast_ray_and_get_normal(
+                wt.translation,
+                -up,
+                wheel.radius + CONTACT_MARGIN,
+                true,
+                filter,
+            );
+            let (contact, normal) = match hit {
+                Some((_e, intersection)) => (intersection.point, intersection.normal),
+                None => {
+                    tyre.grounded = false;
+                    tyre.load = 0.;
+                    tyre.force_longitudinal = 0.;
+                    tyre.force_lateral = 0.;
+                    continue;
+                }
+            };
+            tyre.grounded = true;
+            // the suspension spring carries the load of its corner
+            tyre.load = (suspension.stiffness[i] * suspension.compression[i]).max(0.);
+
+            // wheel heading on the ground plane, the axle follows steering
+            let axle = wt.rotation.mul_vec3(Vec3::Y);
+            let mut forward = axle.cross(normal).normalize_or_zero();
+            if forward.dot(car_forward) < 0. {
+                forward = -forward;
+            }
+            let lateral = normal.cross(forward);
+            let v_long = v.linvel.dot(forward);
+            let v_lat = v.linvel.dot(lateral);
+            let surface_speed = v.angvel.dot(lateral) * wheel.radius;
+
+            tyre.slip_ratio = (surface_speed - v_long) / v_long.abs().max(SLIP_MIN_SPEED);
+            tyre.slip_angle = v_lat.atan2(v_long.abs().max(SLIP_MIN_SPEED));
+
+            let (surface_grip, surface_rolling) =
+                config.surfaces.grip(car.wheel_surface[i], weather.wetness);
+            let load = tyre.load * tyre.grip * surface_grip;
+            let rolling_resistance = tyre.def.rolling_resistance + surface_rolling;
+            // fades out below SLIP_MIN_SPEED so a car at rest is not pushed back and forth
+            let rolling_direction = (v_long / SLIP_MIN_SPEED).clamp(-1., 1.);
+            let mut f_long = tyre.def.longitudinal.eval(tyre.slip_ratio) * load
+                - rolling_resistance * tyre.load * rolling_direction;
+            let mut f_lat = -tyre.def.lateral.eval(tyre.slip_angle) * load;
+            // friction circle
+            let peak = tyre.def.longitudinal.d.max(tyre.def.lateral.d) * load;
+            let total = (f_long.powi(2) + f_lat.powi(2)).sqrt();
+            if total > peak && total > 0. {
+                f_long *= peak / total;
+                f_lat *= peak / total;
+            }
+            tyre.force_longitudinal = f_long;
+            tyre.force_lateral = f_lat;
+
+            let force = forward * f_long + lateral * f_lat;
+            impulse.impulse += force * d_seconds;
+            impulse.torque_impulse += (contact - wt.translation).cross(force) * d_seconds;
+
+            if config.show_rays {
+                lines.line_colored(contact, contact + force / 2000., 0.0, Color::ORANGE);
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{
+    aero::AeroDef, brakes::BrakeDef, drivetrain::PowertrainDef, mesh::obj_mesh, tyre::TyreDef,
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+
+pub const VEHICLE_RACE: &str = "assets/vehicles/race.json";
+pub const VEHICLE_HATCHBACK: &str = "assets/vehicles/hatchback.json";
+pub const VEHICLE_TRUCK: &str = "assets/vehicles/truck.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChassisDef {
+    pub half_width: f32,
+    pub half_height: f32,
+    pub half_length: f32,
+    pub mass: f32,
+    pub center_of_mass: [f32; 3],
+    pub principal_inertia: [f32; 3],
+    pub friction: f32,
+    pub linear_damping: f32,
+    pub angular_damping: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WheelDef {
+    pub radius: f32,
+    pub half_width: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WheelsDef {
+    pub front: WheelDef,
+    pub back: WheelDef,
+    pub mass: f32,
+    pub inertia: f32,
+    pub friction: f32,
+    // distance of the wheel from the chassis side and of the axles from the chassis ends
+    pub side_inset: f32,
+    pub axle_inset: f32,
+    pub ride_height: f32,
+}
+
+// spring rate in N/m, damping in N*s/m, travel in meters up and down from the rest position
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpringDef {
+    pub stiffness: f32,
+    pub damping: f32,
+    pub travel: f32,
+}
+
+// anti-roll bar rates in N/m of compression difference between the left and right wheel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuspensionDef {
+    pub front: SpringDef,
+    pub back: SpringDef,
+    #[serde(default)]
+    pub anti_roll_front: f32,
+    #[serde(default)]
+    pub anti_roll_back: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrivetrainDef {
+    // pedal torque per wheel, drives the car only when there is no powertrain
+    pub max_torque: f32,
+    #[serde(default)]
+    pub powertrain: Option<PowertrainDef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SteeringDef {
+    pub lock_deg: f32,
+    pub speed_limit_kmh: f32,
+}
+
+// glb scenes are loaded as "<path>#Scene0", obj files as a single mesh, no path draws a box
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelDef {
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub offset: [f32; 3],
+    #[serde(default)]
+    pub rotation_y_deg: f32,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    #[serde(default = "default_color")]
+    pub color: [f32; 4],
+}
+fn default_scale() -> f32 {
+    1.
+}
+fn default_color() -> [f32; 4] {
+    [0.8, 0.8, 0.8, 1.]
+}
+
+impl ModelDef {
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(Vec3::from(self.offset))
+            .with_rotation(Quat::from_rotation_y(self.rotation_y_deg.to_radians()))
+            .with_scale(Vec3::ONE * self.scale)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VehicleDef {
+    pub name: String,
+    pub model: ModelDef,
+    pub chassis: ChassisDef,
+    pub wheels: WheelsDef,
+    pub suspension: SuspensionDef,
+    pub drivetrain: DrivetrainDef,
+    pub brakes: BrakeDef,
+    pub aero: AeroDef,
+    pub steering: SteeringDef,
+    // without a tyre model the wheels grip through collider friction only
+    #[serde(default)]
+    pub tyre: Option<TyreDef>,
+}
+
+impl VehicleDef {
+    pub fn load(path: &str) -> Self {
+        let reader = BufReader::new(File::open(path).unwrap());
+        let vehicle: VehicleDef = serde_json::from_reader(reader).unwrap();
+        println!("vehicle: {} from {path}", vehicle.name);
+        vehicle
+    }
+}
+
+#[derive(Clone)]
+pub enum VehicleModel {
+    Scene(Handle<Scene>),
+    Mesh(Handle<Mesh>, Handle<StandardMaterial>),
+}
+
+impl VehicleModel {
+    pub fn load(
+        def: &VehicleDef,
+        asset_server: &AssetServer,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Self {
+        let [r, g, b, a] = def.model.color;
+        let material = materials.add(Color::rgba(r, g, b, a).into());
+        match &def.model.path {
+            Some(path) if path.ends_with(".glb") || path.ends_with(".gltf") => {
+                VehicleModel::Scene(asset_server.load(format!("{path}#Scene0").as_str()))
+            }
+            Some(path) => {
+                VehicleModel::Mesh(meshes.add(obj_mesh(&format!("assets/{path}"))), material)
+            }
+            None => {
+                let c = &def.chassis;
+                VehicleModel::Mesh(
+                    meshes.add(Mesh::from(shape::Box::new(
+                        c.half_width * 2.,
+                        c.half_height * 2.,
+                        c.half_length * 2.,
+                    ))),
+                    material,
+                )
+            }
+        }
+    }
+    pub fn spawn(&self, children: &mut ChildBuilder, transform: Transform) {
+        match self {
+            VehicleModel::Scene(scene) => {
+                children.spawn_bundle(SceneBundle {
+                    scene: scene.clone(),
+                    transform,
+                    ..default()
+                });
+            }
+            VehicleModel::Mesh(mesh, material) => {
+                children.spawn_bundle(PbrBundle {
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    transform,
+                    ..default()
+                });
+            }
+        }
+    }
+}
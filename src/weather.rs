@@ -0,0 +1,142 @@
+use crate::{config::Config, light::Sun};
+use bevy::prelude::*;
+use bevy_atmosphere::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+
+pub const WEATHER_DEFAULT: &str = "assets/weather/default.json";
+
+const SUN_ILLUMINANCE: f32 = 100_000.;
+
+// conditions at a point of the session, values between keyframes are interpolated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeatherKeyframe {
+    pub at_seconds: f32,
+    // hours, 6 is sunrise and 18 sunset
+    pub time_of_day: f32,
+    // 0..1, rain makes the track wet
+    pub rain: f32,
+    // 0..1, fog shortens the sensor range down to fog_visibility
+    pub fog: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeatherScript {
+    pub name: String,
+    // the script restarts after this many seconds, it holds the last keyframe without it
+    #[serde(default)]
+    pub loop_seconds: Option<f32>,
+    pub fog_visibility: f32,
+    pub keyframes: Vec<WeatherKeyframe>,
+}
+
+impl WeatherScript {
+    pub fn load(path: &str) -> Self {
+        let reader = BufReader::new(File::open(path).unwrap());
+        let script: WeatherScript = serde_json::from_reader(reader).unwrap();
+        println!(
+            "weather: {} keyframes: {}",
+            script.name,
+            script.keyframes.len()
+        );
+        script
+    }
+    pub fn sample(&self, seconds: f32) -> WeatherKeyframe {
+        let seconds = match self.loop_seconds {
+            Some(l) if l > 0. => seconds.rem_euclid(l),
+            _ => seconds,
+        };
+        let frames = &self.keyframes;
+        match frames.iter().position(|k| k.at_seconds > seconds) {
+            None => frames.last().cloned().unwrap_or(CLEAR_NOON),
+            Some(0) => frames[0].clone(),
+            Some(i) => {
+                let (a, b) = (&frames[i - 1], &frames[i]);
+                let x = (seconds - a.at_seconds) / (b.at_seconds - a.at_seconds);
+                WeatherKeyframe {
+                    at_seconds: seconds,
+                    time_of_day: a.time_of_day + (b.time_of_day - a.time_of_day) * x,
+                    rain: a.rain + (b.rain - a.rain) * x,
+                    fog: a.fog + (b.fog - a.fog) * x,
+                }
+            }
+        }
+    }
+}
+
+const CLEAR_NOON: WeatherKeyframe = WeatherKeyframe {
+    at_seconds: 0.,
+    time_of_day: 12.,
+    rain: 0.,
+    fog: 0.,
+};
+
+pub struct Weather {
+    pub script: Option<WeatherScript>,
+    pub current: WeatherKeyframe,
+    // 0 dry .. 1 fully wet, follows the rain
+    pub wetness: f32,
+    // script seconds set by replay playback, the session time without it
+    pub clock: Option<f32>,
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self {
+            script: None,
+            current: CLEAR_NOON,
+            wetness: 0.,
+            clock: None,
+        }
+    }
+}
+
+impl Weather {
+    // sensor range left in the current fog
+    pub fn visibility(&self, max_range: f32) -> f32 {
+        let fog_visibility = match &self.script {
+            Some(script) => script.fog_visibility.min(max_range),
+            None => max_range,
+        };
+        max_range + (fog_visibility - max_range) * self.current.fog
+    }
+    // unit vector towards the sun, below the horizon at night
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = (self.current.time_of_day - 6.) / 12. * PI;
+        Vec3::new(angle.cos(), angle.sin(), 0.3).normalize()
+    }
+}
+
+pub fn weather_start_system(config: Res<Config>, mut weather: ResMut<Weather>) {
+    weather.script = Some(WeatherScript::load(&config.weather_path));
+}
+
+pub fn weather_system(
+    time: Res<Time>,
+    mut weather: ResMut<Weather>,
+    mut atmosphere: ResMut<Atmosphere>,
+    mut q_sun: Query<(&mut DirectionalLight, &mut Transform), With<Sun>>,
+) {
+    let seconds = weather.clock.unwrap_or(time.seconds_since_startup() as f32);
+    let current = match &weather.script {
+        Some(script) => script.sample(seconds),
+        None => return,
+    };
+    weather.wetness = current.rain;
+    weather.current = current;
+
+    let sun = weather.sun_direction();
+    // the sky is re-rendered on every change, skip tiny sun moves
+    if atmosphere.sun_position.distance(sun) > 0.001 {
+        atmosphere.sun_position = sun;
+    }
+    let daylight = sun.y.max(0.);
+    let overcast = 1. - 0.5 * weather.current.rain - 0.3 * weather.current.fog;
+    for (mut light, mut transform) in q_sun.iter_mut() {
+        light.illuminance = SUN_ILLUMINANCE * daylight * overcast;
+        *transform = Transform::from_translation(transform.translation)
+            .looking_at(transform.translation - sun, Vec3::Y);
+    }
+}